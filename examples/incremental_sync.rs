@@ -3,7 +3,7 @@
 //! Run with: cargo run --example incremental_sync --features io
 
 // Only compile this example with sync IO
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use stainless_facts::{Fact, FactStore, Operation};
 use std::thread;
@@ -18,14 +18,13 @@ enum Event {
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let store: FactStore<String, Event, String> = FactStore::open_or_create("sync_example.facts")?;
 
-    // Simulate initial sync
-    let mut last_sync = DateTime::<Utc>::MIN_UTC;
-
+    // Simulate initial sync, remembering where it ended
     println!("=== Initial sync ===");
-    for fact in store.iter_from(last_sync) {
+    let mut facts = store.iter();
+    for fact in facts.by_ref() {
         println!("  {:?}", fact);
-        last_sync = *fact.timestamp();
     }
+    let last_sync = facts.cursor();
 
     // Add new fact
     thread::sleep(Duration::from_millis(100));
@@ -38,9 +37,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
     store.append(new_fact)?;
 
-    // Incremental sync - only new facts
+    // Incremental sync - only new facts, even if some share a timestamp
+    // with the last one seen
     println!("\n=== Incremental sync (only new facts) ===");
-    for fact in store.iter_from(last_sync) {
+    let new_facts = match last_sync {
+        Some(cursor) => store.iter_from_cursor(cursor),
+        None => store.iter(),
+    };
+    for fact in new_facts {
         println!("  {:?}", fact);
     }
 
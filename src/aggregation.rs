@@ -0,0 +1,262 @@
+// stainless-facts: incremental aggregation
+// =========================================
+//
+// Aggregates kept up to date fact by fact, instead of rebuilt from the
+// whole store after every append.
+
+use crate::{
+    apply_fact,
+    io::Codec,
+    snapshot::{self, Snapshot},
+    store::{FactStore, StoreError},
+    Cursor, Fact, FactAggregator,
+};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Aggregators for every entity, kept up to date as facts arrive.
+///
+/// The long-lived counterpart of [`aggregate_facts`](crate::aggregate_facts):
+/// facts are applied one at a time or in batches, and
+/// [`Aggregation::catch_up`] pulls only the facts a store gained since the
+/// last call. The aggregate of an entity can be looked up at any time.
+///
+/// The position in the store is the [`Cursor`] after the last fact
+/// `catch_up` applied. Facts passed to [`Aggregation::apply`] do not move
+/// it, so apply the facts of a store through one or the other, not both.
+///
+/// # Example
+///
+/// ```no_run
+/// # use stainless_facts::{Aggregation, FactStore};
+/// # #[derive(Default)]
+/// # struct Track { plays: u32 }
+/// # impl stainless_facts::FactAggregator<String, String, String> for Track {
+/// #     fn assert(&mut self, _: &String, _: &String) { self.plays += 1 }
+/// #     fn retract(&mut self, _: &String, _: &String) {}
+/// # }
+/// # fn example(store: FactStore<String, String, String>) -> Result<(), stainless_facts::StoreError> {
+/// let mut tracks: Aggregation<String, String, String, Track> = Aggregation::new();
+/// tracks.catch_up(&store)?;
+///
+/// // ... facts are appended
+/// let applied = tracks.catch_up(&store)?; // only the new ones
+/// if let Some(track) = tracks.get(&"track1".to_string()) {
+///     println!("{} plays", track.plays);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Aggregation<E, V, S, A> {
+    aggregators: HashMap<E, A>,
+    /// Position after the last fact `catch_up` applied
+    position: Option<Cursor>,
+    /// Timestamp of the last fact applied
+    latest_timestamp: Option<DateTime<Utc>>,
+    _phantom: PhantomData<(V, S)>,
+}
+
+impl<E, V, S, A> Default for Aggregation<E, V, S, A> {
+    fn default() -> Self {
+        Self {
+            aggregators: HashMap::new(),
+            position: None,
+            latest_timestamp: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<E, V, S, A> Aggregation<E, V, S, A> {
+    /// An aggregation that has not applied any facts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carry on from a snapshot: its state, and its watermark as the
+    /// position in the store.
+    pub fn from_snapshot(snapshot: Snapshot<E, A>) -> Self {
+        let position = snapshot.watermark();
+        Self {
+            aggregators: snapshot.into_state(),
+            position,
+            latest_timestamp: position.map(|cursor| cursor.timestamp()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// A snapshot of the current state, up to the position in the store.
+    pub fn snapshot(&self) -> Snapshot<E, A>
+    where
+        E: Clone,
+        A: Clone,
+    {
+        Snapshot::new(self.aggregators.clone(), self.position)
+    }
+
+    /// The current state, as a snapshot up to the position in the store.
+    pub fn into_snapshot(self) -> Snapshot<E, A> {
+        Snapshot::new(self.aggregators, self.position)
+    }
+
+    /// The aggregators of every entity.
+    pub fn aggregators(&self) -> &HashMap<E, A> {
+        &self.aggregators
+    }
+
+    /// Take the aggregators of every entity.
+    pub fn into_aggregators(self) -> HashMap<E, A> {
+        self.aggregators
+    }
+
+    /// Position after the last fact [`Aggregation::catch_up`] applied.
+    pub fn position(&self) -> Option<Cursor> {
+        self.position
+    }
+
+    /// Timestamp of the last fact applied, however it was applied.
+    pub fn latest_timestamp(&self) -> Option<DateTime<Utc>> {
+        self.latest_timestamp
+    }
+}
+
+impl<E, V, S, A> Aggregation<E, V, S, A>
+where
+    E: Eq + Hash + Clone,
+    A: FactAggregator<E, V, S> + Default,
+{
+    /// The current aggregate of `entity`, if any fact about it was applied.
+    pub fn get(&self, entity: &E) -> Option<&A> {
+        self.aggregators.get(entity)
+    }
+
+    /// Apply one fact to the aggregator of its entity.
+    pub fn apply(&mut self, fact: &Fact<E, V, S>) {
+        apply_fact(&mut self.aggregators, fact);
+        self.latest_timestamp = Some(*fact.timestamp());
+    }
+
+    /// Apply facts in order.
+    pub fn apply_batch(&mut self, facts: &[Fact<E, V, S>]) {
+        for fact in facts {
+            self.apply(fact);
+        }
+    }
+
+    /// Apply the facts `store` gained since the last call, and return how
+    /// many there were.
+    ///
+    /// The first call applies every fact in the store, or every fact after
+    /// the watermark of the snapshot the aggregation was created from. Only
+    /// facts that were in the store when the call started are applied.
+    ///
+    /// Fails with [`StoreError::CursorMismatch`], without applying anything,
+    /// if the store was rewritten since the last call so that the position
+    /// no longer matches; the aggregation must then be rebuilt. Should a
+    /// fact fail to read, the facts before it stay applied and the position
+    /// moves past them, so the next call picks up where this one stopped.
+    pub fn catch_up<C>(&mut self, store: &FactStore<E, V, S, C>) -> Result<u64, StoreError>
+    where
+        E: Serialize + DeserializeOwned,
+        V: Serialize + DeserializeOwned + Clone,
+        S: Serialize + DeserializeOwned + Clone,
+        C: Codec,
+    {
+        let before = self.position;
+        let result = snapshot::replay(&mut self.aggregators, &mut self.position, store);
+        if self.position != before {
+            self.latest_timestamp = self.position.map(|cursor| cursor.timestamp());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_entity_facts, entity_fact};
+    use crate::{aggregate_facts, Operation};
+    use tempfile::NamedTempFile;
+
+    /// Values asserted and not retracted.
+    #[derive(Debug, Default, Clone, PartialEq)]
+    struct Values(Vec<u32>);
+
+    impl FactAggregator<String, u32, String> for Values {
+        fn assert(&mut self, value: &u32, _source: &String) {
+            self.0.push(*value);
+        }
+
+        fn retract(&mut self, value: &u32, _source: &String) {
+            self.0.retain(|v| v != value);
+        }
+    }
+
+    #[test]
+    fn apply_updates_single_entities() {
+        let mut aggregation: Aggregation<String, u32, String, Values> = Aggregation::new();
+        aggregation.apply_batch(&[
+            entity_fact("a", 1, Operation::Assert),
+            entity_fact("b", 2, Operation::Assert),
+        ]);
+        aggregation.apply(&entity_fact("a", 3, Operation::Assert));
+        aggregation.apply(&entity_fact("a", 1, Operation::Retract));
+
+        assert_eq!(aggregation.get(&"a".to_string()), Some(&Values(vec![3])));
+        assert_eq!(aggregation.get(&"b".to_string()), Some(&Values(vec![2])));
+        assert_eq!(aggregation.get(&"c".to_string()), None);
+        assert_eq!(
+            aggregation.latest_timestamp(),
+            Some(*entity_fact("a", 1, Operation::Retract).timestamp())
+        );
+        assert_eq!(aggregation.position(), None);
+    }
+
+    #[test]
+    fn catch_up_applies_only_new_facts() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_entity_facts(9);
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        let mut aggregation: Aggregation<String, u32, String, Values> = Aggregation::new();
+        assert_eq!(aggregation.catch_up(&store).unwrap(), 0);
+
+        store.append_batch(&facts[..4]).unwrap();
+        assert_eq!(aggregation.catch_up(&store).unwrap(), 4);
+        assert_eq!(aggregation.catch_up(&store).unwrap(), 0);
+        assert_eq!(
+            aggregation.get(&"entity0".to_string()),
+            Some(&Values(vec![0, 3]))
+        );
+
+        store.append_batch(&facts[4..]).unwrap();
+        assert_eq!(aggregation.catch_up(&store).unwrap(), 5);
+        assert_eq!(aggregation.aggregators(), &aggregate_facts(facts.clone()));
+        assert_eq!(aggregation.latest_timestamp(), Some(*facts[8].timestamp()));
+        assert_eq!(
+            aggregation.position().map(|cursor| cursor.timestamp()),
+            Some(*facts[8].timestamp())
+        );
+    }
+
+    #[test]
+    fn catch_up_resumes_from_a_snapshot() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_entity_facts(6);
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&facts[..3]).unwrap();
+
+        let mut aggregation: Aggregation<String, u32, String, Values> = Aggregation::new();
+        aggregation.catch_up(&store).unwrap();
+        let snapshot = aggregation.snapshot();
+        store.append_batch(&facts[3..]).unwrap();
+
+        let mut resumed = Aggregation::from_snapshot(snapshot);
+        assert_eq!(resumed.catch_up(&store).unwrap(), 3);
+        aggregation.catch_up(&store).unwrap();
+        assert_eq!(resumed.aggregators(), aggregation.aggregators());
+        assert_eq!(resumed.into_snapshot(), aggregation.into_snapshot());
+    }
+}
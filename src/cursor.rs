@@ -0,0 +1,106 @@
+// stainless-facts: resumable positions in a store
+// ===============================================
+//
+// A cursor marks one fact on disk, so iteration can resume right after it
+// even when other facts share its timestamp.
+
+use crate::Fact;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Position just after a fact read from a store.
+///
+/// Returned by the `cursor` method of the store iterators, and accepted by
+/// [`FactStore::iter_from_cursor`](crate::FactStore::iter_from_cursor) to
+/// carry on exactly after that fact. Unlike a timestamp, a cursor tells apart
+/// facts that share one, so resuming from it neither repeats nor skips any.
+///
+/// A cursor records where its fact is stored and enough about it to notice
+/// when the store was rewritten since: resuming from a cursor whose fact is
+/// no longer where it was fails with [`StoreError::CursorMismatch`].
+/// Cursors serialize with serde, so they can be kept between runs.
+///
+/// [`StoreError::CursorMismatch`]: crate::StoreError::CursorMismatch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Sequence number of the segment file, 0 for an unsegmented store
+    segment: usize,
+    /// Byte offset of the line holding the fact
+    offset: u64,
+    /// Index of the fact within its line, for framed batches
+    ordinal: usize,
+    timestamp: DateTime<Utc>,
+    /// CRC32C of the whole line, to notice it being replaced
+    checksum: u32,
+}
+
+impl Cursor {
+    /// Timestamp of the fact the cursor points after.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub(crate) fn segment(&self) -> usize {
+        self.segment
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether `line`, read at the cursor's position and decoded into
+    /// `facts`, is still the line the cursor was taken on.
+    pub(crate) fn matches<E, V, S>(&self, line: &[u8], facts: &VecDeque<Fact<E, V, S>>) -> bool {
+        crc32c::crc32c(line) == self.checksum
+            && facts
+                .get(self.ordinal)
+                .is_some_and(|fact| fact.timestamp() == &self.timestamp)
+    }
+
+    /// Number of facts on the cursor's line up to and including its own.
+    pub(crate) fn consumed(&self) -> usize {
+        self.ordinal + 1
+    }
+}
+
+/// The line a store iterator is reading facts from.
+///
+/// Tracks which fact of the line was yielded last, to build its cursor, and
+/// how many facts came before it since the iterator started.
+#[derive(Debug, Default)]
+pub(crate) struct LinePosition {
+    offset: u64,
+    checksum: u32,
+    /// Facts decoded from the line
+    facts: usize,
+    /// Facts decoded from earlier lines
+    before: u64,
+}
+
+impl LinePosition {
+    /// Start on the line at `offset` that decoded into `facts` facts.
+    pub(crate) fn start(&mut self, offset: u64, line: &[u8], facts: usize) {
+        self.before += self.facts as u64;
+        self.offset = offset;
+        self.checksum = crc32c::crc32c(line);
+        self.facts = facts;
+    }
+
+    /// Number of facts decoded before the one just taken off the line, with
+    /// `left` still to go.
+    pub(crate) fn taken(&self, left: usize) -> u64 {
+        self.before + (self.facts - left - 1) as u64
+    }
+
+    /// Cursor for the fact just taken off the line, with `left` still to go.
+    pub(crate) fn cursor(&self, segment: usize, left: usize, timestamp: DateTime<Utc>) -> Cursor {
+        Cursor {
+            segment,
+            offset: self.offset,
+            ordinal: self.facts - left - 1,
+            timestamp,
+            checksum: self.checksum,
+        }
+    }
+}
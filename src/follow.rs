@@ -0,0 +1,379 @@
+// stainless-facts: following a FactStore
+// ======================================
+//
+// Blocking iteration that waits for new facts at the end of the store.
+
+use crate::io::Codec;
+use crate::store::{StoreError, TryFactIterator};
+use crate::Fact;
+use parking_lot::{Condvar, Mutex};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often to look for new facts when no file notifications are available.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often to look anyway when notifications are, in case one is missed.
+#[cfg(feature = "notify")]
+const WATCHED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wakes a waiting [`FollowIterator`] when the store changes or is cancelled.
+#[derive(Default)]
+struct Signal {
+    state: Mutex<SignalState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct SignalState {
+    cancelled: bool,
+    notified: bool,
+}
+
+impl Signal {
+    #[cfg(feature = "notify")]
+    fn notify(&self) {
+        self.state.lock().notified = true;
+        self.changed.notify_all();
+    }
+
+    fn cancel(&self) {
+        self.state.lock().cancelled = true;
+        self.changed.notify_all();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.state.lock().cancelled
+    }
+
+    /// Wait for a notification or cancellation, but no longer than `timeout`.
+    fn wait(&self, timeout: Duration) {
+        let mut state = self.state.lock();
+        if !state.notified && !state.cancelled {
+            self.changed.wait_for(&mut state, timeout);
+        }
+        state.notified = false;
+    }
+}
+
+/// Cancels a [`FollowIterator`], possibly from another thread.
+///
+/// A cancelled iterator returns `None` from every further call to `next`,
+/// including one that is currently blocked waiting for facts.
+#[derive(Clone)]
+pub struct FollowHandle {
+    signal: Arc<Signal>,
+}
+
+impl FollowHandle {
+    /// Stop the iterator for good.
+    pub fn cancel(&self) {
+        self.signal.cancel();
+    }
+
+    /// Whether [`FollowHandle::cancel`] has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.signal.is_cancelled()
+    }
+}
+
+/// Iterator over the facts of a store that waits for more at the end.
+///
+/// Created by [`FactStore::follow`](crate::FactStore::follow). Instead of
+/// ending when it has caught up, `next` blocks until another fact is
+/// appended. A line that is still being written is not read until it is
+/// complete. Iteration ends after the first read error, when cancelled
+/// through a [`FollowHandle`], or when a timeout set with
+/// [`FollowIterator::with_timeout`] passes without a new fact.
+///
+/// With the `notify` feature the store's directory is watched for changes
+/// (inotify on Linux, FSEvents or kqueue on macOS, ReadDirectoryChangesW on
+/// Windows). Without it, or if watching fails, the file is polled.
+pub struct FollowIterator<E, V, S, C> {
+    inner: TryFactIterator<E, V, S, C>,
+    signal: Arc<Signal>,
+    timeout: Option<Duration>,
+    poll_interval: Duration,
+    #[cfg(feature = "notify")]
+    _watcher: Option<notify::RecommendedWatcher>,
+}
+
+impl<E, V, S, C> FollowIterator<E, V, S, C>
+where
+    C: Codec,
+    E: DeserializeOwned + Clone,
+    V: DeserializeOwned + Clone,
+    S: DeserializeOwned + Clone,
+{
+    /// Follow `inner`, watching `dir` for changes where supported.
+    pub(crate) fn new(inner: TryFactIterator<E, V, S, C>, dir: &Path) -> Self {
+        let signal = Arc::new(Signal::default());
+
+        #[cfg(feature = "notify")]
+        {
+            let watcher = watch(dir, Arc::clone(&signal));
+            let poll_interval = if watcher.is_some() {
+                WATCHED_POLL_INTERVAL
+            } else {
+                POLL_INTERVAL
+            };
+            Self {
+                inner,
+                signal,
+                timeout: None,
+                poll_interval,
+                _watcher: watcher,
+            }
+        }
+        #[cfg(not(feature = "notify"))]
+        {
+            let _ = dir;
+            Self {
+                inner,
+                signal,
+                timeout: None,
+                poll_interval: POLL_INTERVAL,
+            }
+        }
+    }
+
+    /// End the iteration when no new fact arrives within `timeout`.
+    ///
+    /// The timeout applies to each call to `next` separately. Unlike a
+    /// cancellation, it does not end the iteration for good: calling `next`
+    /// again waits for another `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Look for new facts at this interval.
+    ///
+    /// This is how quickly appends are noticed without file notifications.
+    /// With them, it only bounds the delay if a notification is missed.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// A handle that cancels this iterator.
+    pub fn handle(&self) -> FollowHandle {
+        FollowHandle {
+            signal: Arc::clone(&self.signal),
+        }
+    }
+}
+
+impl<E, V, S, C> Iterator for FollowIterator<E, V, S, C>
+where
+    C: Codec,
+    E: DeserializeOwned + Clone,
+    V: DeserializeOwned + Clone,
+    S: DeserializeOwned + Clone,
+{
+    type Item = Result<Fact<E, V, S>, StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if self.signal.is_cancelled() {
+                return None;
+            }
+            if let Some(item) = self.inner.next() {
+                return Some(item);
+            }
+            if self.inner.is_stopped() {
+                return None;
+            }
+
+            let wait = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    (deadline - now).min(self.poll_interval)
+                }
+                None => self.poll_interval,
+            };
+            self.signal.wait(wait);
+        }
+    }
+}
+
+/// Signal every change in `dir`, or `None` if it cannot be watched.
+#[cfg(feature = "notify")]
+fn watch(dir: &Path, signal: Arc<Signal>) -> Option<notify::RecommendedWatcher> {
+    use notify::Watcher;
+
+    let mut watcher = notify::recommended_watcher(move |_: notify::Result<notify::Event>| {
+        signal.notify();
+    })
+    .ok()?;
+    watcher
+        .watch(dir, notify::RecursiveMode::NonRecursive)
+        .ok()?;
+    Some(watcher)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{create_many_facts, TestFact, TestValue};
+    use crate::{FactStore, SegmentPolicy};
+    use chrono::{DateTime, Utc};
+    use std::io::Write;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    fn take(
+        iter: impl Iterator<Item = Result<TestFact, crate::StoreError>>,
+        n: usize,
+    ) -> Vec<TestFact> {
+        iter.take(n).map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn follows_facts_appended_by_another_thread() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let facts = create_many_facts(20);
+        let store = FactStore::open_or_create(&path).unwrap();
+        store.append_batch(&facts[..5]).unwrap();
+
+        let follow = store
+            .follow(DateTime::<Utc>::MIN_UTC)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_secs(10));
+
+        let writer = {
+            let facts = facts.clone();
+            thread::spawn(move || {
+                let store = FactStore::open_or_create(&path).unwrap();
+                for fact in &facts[5..] {
+                    thread::sleep(Duration::from_millis(2));
+                    store.append(fact.clone()).unwrap();
+                }
+            })
+        };
+
+        assert_eq!(take(follow, 20), facts);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn follows_a_store_that_does_not_exist_yet() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let store = FactStore::open_or_create(&path).unwrap();
+        let facts = create_many_facts(3);
+
+        let mut follow = store
+            .follow(*facts[1].timestamp())
+            .with_poll_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_millis(50));
+        assert!(follow.next().is_none());
+
+        store.append_batch(&facts).unwrap();
+        assert_eq!(take(follow, 2), facts[1..]);
+    }
+
+    #[test]
+    fn resumes_after_a_partially_written_line() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let facts = create_many_facts(3);
+        let store = FactStore::open_or_create(&path).unwrap();
+        store.append(facts[0].clone()).unwrap();
+
+        let mut follow = store
+            .follow(DateTime::<Utc>::MIN_UTC)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_millis(50));
+        assert_eq!(follow.next().unwrap().unwrap(), facts[0]);
+
+        let mut line = serde_json::to_vec(&facts[1]).unwrap();
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        file.write_all(&line[..line.len() / 2]).unwrap();
+        assert!(follow.next().is_none());
+
+        file.write_all(&line[line.len() / 2..]).unwrap();
+        assert_eq!(follow.next().unwrap().unwrap(), facts[1]);
+
+        store.append(facts[2].clone()).unwrap();
+        assert_eq!(follow.next().unwrap().unwrap(), facts[2]);
+    }
+
+    #[test]
+    fn timeout_ends_each_wait_but_not_the_iteration() {
+        let dir = TempDir::new().unwrap();
+        let store = FactStore::open_or_create(dir.path().join("facts.stream")).unwrap();
+        let facts = create_many_facts(2);
+        store.append(facts[0].clone()).unwrap();
+
+        let mut follow = store
+            .follow(DateTime::<Utc>::MIN_UTC)
+            .with_timeout(Duration::from_millis(100));
+        assert_eq!(follow.next().unwrap().unwrap(), facts[0]);
+
+        let started = Instant::now();
+        assert!(follow.next().is_none());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        store.append(facts[1].clone()).unwrap();
+        assert_eq!(follow.next().unwrap().unwrap(), facts[1]);
+    }
+
+    #[test]
+    fn cancel_wakes_a_blocked_iterator() {
+        let dir = TempDir::new().unwrap();
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_or_create(dir.path().join("facts.stream")).unwrap();
+
+        let mut follow = store
+            .follow(DateTime::<Utc>::MIN_UTC)
+            .with_poll_interval(Duration::from_secs(60));
+        let handle = follow.handle();
+
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.cancel();
+        });
+        let started = Instant::now();
+        assert!(follow.next().is_none());
+        assert!(started.elapsed() < Duration::from_secs(30));
+        canceller.join().unwrap();
+
+        assert!(follow.handle().is_cancelled());
+        store.append(create_many_facts(1)[0].clone()).unwrap();
+        assert!(follow.next().is_none());
+    }
+
+    #[test]
+    fn follows_across_segment_rolls() {
+        let dir = TempDir::new().unwrap();
+        let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500)).unwrap();
+        let facts = create_many_facts(40);
+        store.append_batch(&facts[..3]).unwrap();
+
+        let mut follow = store
+            .follow(DateTime::<Utc>::MIN_UTC)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_timeout(Duration::from_millis(50));
+        assert_eq!(take(follow.by_ref(), 3), facts[..3]);
+
+        for batch in facts[3..].chunks(3) {
+            store.append_batch(batch).unwrap();
+        }
+        assert!(std::fs::read_dir(dir.path()).unwrap().count() > 3);
+
+        assert_eq!(take(follow.by_ref(), 37), facts[3..]);
+        assert!(follow.next().is_none());
+    }
+}
@@ -0,0 +1,281 @@
+// stainless-facts: group commit for AsyncFactStore
+// ================================================
+//
+// A writer task that owns the store file and writes every batch queued
+// while it was busy with one write and one fsync.
+
+use crate::io::{
+    common::{self, LineFormat},
+    Codec, FactStreamWriter, SyncSchedule, WriteError,
+};
+use crate::options::OrderingPolicy;
+use crate::store::{FactStore, SequenceMark, StoreError};
+use crate::subscription::CommittedBatch;
+use crate::Fact;
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// Batches that can wait for the writer task before appends are held back.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Most batches written together, so one group cannot grow without bound.
+const MAX_GROUP: usize = 256;
+
+/// Starts the writer task of a store.
+///
+/// A function pointer, chosen where `Send` bounds are known, so that
+/// appending needs none.
+pub(crate) type SpawnWriter<E, V, S, C> = fn(GroupCommit<E, V, S, C>) -> Writer<E, V, S>;
+
+/// Facts appended together, shared with subscribers once written.
+type Batch<E, V, S> = Arc<[Fact<E, V, S>]>;
+
+/// Queue of batches for a writer task.
+pub(crate) type Writer<E, V, S> = mpsc::Sender<AppendRequest<E, V, S>>;
+
+/// Result of a batch: the sequence numbers assigned to its facts.
+type Appended = Result<Range<u64>, StoreError>;
+
+/// A batch waiting to be written, and where to send its result.
+///
+/// An empty batch asks for everything written before it to be synced.
+pub(crate) struct AppendRequest<E, V, S> {
+    facts: Batch<E, V, S>,
+    reply: oneshot::Sender<Appended>,
+}
+
+/// Append `facts` through the writer task and wait for its result.
+pub(crate) async fn append<E, V, S>(writer: &Writer<E, V, S>, facts: Batch<E, V, S>) -> Appended {
+    debug_assert!(!facts.is_empty());
+    request(writer, facts).await
+}
+
+/// Sync everything the writer task has written, once the batches queued
+/// before this are written too.
+pub(crate) async fn flush_durable<E, V, S>(writer: &Writer<E, V, S>) -> Result<(), StoreError> {
+    request(writer, Vec::new().into()).await.map(|_| ())
+}
+
+async fn request<E, V, S>(writer: &Writer<E, V, S>, facts: Batch<E, V, S>) -> Appended {
+    let (reply, result) = oneshot::channel();
+    if writer.send(AppendRequest { facts, reply }).await.is_err() {
+        return Err(stopped());
+    }
+    result.await.unwrap_or_else(|_| Err(stopped()))
+}
+
+fn stopped() -> StoreError {
+    std::io::Error::new(ErrorKind::Other, "the store's writer task has stopped").into()
+}
+
+/// What the writer task shares with its store.
+pub(crate) struct GroupCommit<E, V, S, C> {
+    pub(crate) path: PathBuf,
+    pub(crate) codec: C,
+    pub(crate) format: LineFormat,
+    pub(crate) schedule: SyncSchedule,
+    pub(crate) ordering: OrderingPolicy,
+    pub(crate) lock_timeout: Duration,
+    pub(crate) retry_interval: Duration,
+    pub(crate) latest_timestamp: Arc<RwLock<Option<DateTime<Utc>>>>,
+    pub(crate) committed: broadcast::Sender<CommittedBatch<E, V, S>>,
+    pub(crate) committed_len: Arc<Mutex<u64>>,
+    pub(crate) appends: Arc<Mutex<Option<SequenceMark>>>,
+}
+
+impl<E, V, S, C> GroupCommit<E, V, S, C>
+where
+    E: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    V: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    S: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    C: Codec,
+{
+    /// Start the writer task on the current tokio runtime.
+    ///
+    /// The task stops once every sender of its queue has been dropped.
+    pub(crate) fn spawn(self) -> Writer<E, V, S> {
+        let (writer, requests) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(self.run(requests));
+        writer
+    }
+
+    async fn run(self, mut requests: mpsc::Receiver<AppendRequest<E, V, S>>) {
+        let commit = Arc::new(self);
+        let mut file = None;
+
+        while let Some(first) = requests.recv().await {
+            // Everything that queued up during the last write goes in this one
+            let mut group = vec![first];
+            while group.len() < MAX_GROUP {
+                match requests.try_recv() {
+                    Ok(request) => group.push(request),
+                    Err(_) => break,
+                }
+            }
+
+            let batches: Vec<_> = group.iter().map(|r| Arc::clone(&r.facts)).collect();
+            let task = Arc::clone(&commit);
+            let taken = file.take();
+            let results =
+                match tokio::task::spawn_blocking(move || task.write(taken, &batches)).await {
+                    Ok((writer, results)) => {
+                        file = writer;
+                        results
+                    }
+                    Err(e) => {
+                        let failed = shared_error(std::io::Error::new(ErrorKind::Other, e).into());
+                        group.iter().map(|_| Err(failed())).collect()
+                    }
+                };
+
+            for (request, result) in group.into_iter().zip(results) {
+                // The caller may have stopped waiting; the batch is written anyway
+                let _ = request.reply.send(result);
+            }
+        }
+    }
+
+    /// Write `batches` with one write and at most one fsync, returning the
+    /// result of each, and the writer to use next time.
+    ///
+    /// Accepted batches are numbered in the order they were written, after
+    /// the facts already in the file.
+    ///
+    /// Every batch is checked for ordering on its own, against the end of
+    /// the file and the batches before it in the group, so a rejected batch
+    /// does not hold back the others. The group is synced according to the
+    /// durability policy, counting each batch, or always if it holds a
+    /// request to flush.
+    fn write(
+        &self,
+        writer: Option<FactStreamWriter<C>>,
+        batches: &[Batch<E, V, S>],
+    ) -> (Option<FactStreamWriter<C>>, Vec<Appended>) {
+        let mut writer = match writer {
+            Some(writer) => writer,
+            None => match FactStreamWriter::open(&self.path) {
+                Ok(writer) => writer
+                    .with_format(self.format)
+                    .with_codec(self.codec.clone())
+                    .with_lock_wait(self.lock_timeout, self.retry_interval)
+                    .with_schedule(self.schedule),
+                Err(e) => {
+                    let failed = shared_error(e.into());
+                    return (None, batches.iter().map(|_| Err(failed())).collect());
+                }
+            },
+        };
+
+        // Encoded batches, or why each was rejected
+        let mut outcomes: Vec<Result<Vec<u8>, StoreError>> = batches
+            .iter()
+            .map(|facts| {
+                common::serialize_batch(&self.codec, facts, self.format)
+                    .map_err(|e| WriteError::from(e).into())
+            })
+            .collect();
+
+        let written = writer.append_with(|file| {
+            let mut latest = FactStore::<E, V, S, C>::read_latest_timestamp(&self.codec, file)?;
+            *self.latest_timestamp.write() = latest;
+
+            let mut buffer = Vec::new();
+            for (facts, outcome) in batches.iter().zip(&mut outcomes) {
+                let Ok(encoded) = outcome else { continue };
+                match self.ordering.check(facts, latest) {
+                    Ok(()) => {
+                        buffer.extend_from_slice(encoded);
+                        latest = facts.last().map(|fact| *fact.timestamp()).or(latest);
+                    }
+                    Err(e) => *outcome = Err(e),
+                }
+            }
+            Ok::<_, StoreError>(buffer)
+        });
+
+        let accepted = batches
+            .iter()
+            .zip(&outcomes)
+            .filter(|(facts, outcome)| !facts.is_empty() && outcome.is_ok())
+            .count();
+        let written = written.and_then(|written| {
+            if batches.iter().any(|facts| facts.is_empty()) {
+                writer.flush_durable()?;
+            } else {
+                writer.written(accepted)?;
+            }
+            Ok(written)
+        });
+
+        let written = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let failed = shared_error(e);
+                let results = outcomes
+                    .into_iter()
+                    .map(|outcome| outcome.and_then(|_| Err(failed())))
+                    .collect();
+                // Open the file afresh next time, in case it was left in a bad state
+                return (None, results);
+            }
+        };
+
+        // Another process may have appended since the last group. The group
+        // is written even if it cannot be numbered, and stays visible
+        let mark = self.appends.lock().clone();
+        let (mut seq, uncounted) =
+            match SequenceMark::seq_at(mark.as_ref(), &self.codec, &self.path, written.start) {
+                Ok(seq) => (seq, None),
+                Err(e) => (0, Some(shared_error(e.into()))),
+            };
+
+        // Under the lock, so a new subscriber either gets a batch or starts after it
+        let mut committed_len = self.committed_len.lock();
+        let mut end = written.start;
+        let results = batches
+            .iter()
+            .zip(outcomes)
+            .map(|(facts, outcome)| {
+                end += outcome?.len() as u64;
+                let Some(last) = facts.last() else {
+                    return Ok(seq..seq);
+                };
+                *committed_len = end;
+                *self.latest_timestamp.write() = Some(*last.timestamp());
+                if self.committed.receiver_count() > 0 {
+                    // Nobody listening is not an error
+                    let _ = self
+                        .committed
+                        .send(CommittedBatch::new(Arc::clone(facts), end));
+                }
+                if let Some(failed) = &uncounted {
+                    return Err(failed());
+                }
+                let first = seq;
+                seq += facts.len() as u64;
+                Ok(first..seq)
+            })
+            .collect();
+        drop(committed_len);
+
+        *self.appends.lock() = uncounted.is_none().then(|| SequenceMark {
+            path: self.path.clone(),
+            offset: written.end,
+            seq,
+        });
+        (Some(writer), results)
+    }
+}
+
+/// The error of a group that failed as a whole, shared by each of its batches.
+fn shared_error(error: StoreError) -> impl Fn() -> StoreError {
+    let error = Arc::new(error);
+    move || StoreError::GroupCommit(Arc::clone(&error))
+}
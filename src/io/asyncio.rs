@@ -1,16 +1,65 @@
-use super::{common, ReadError, WriteError};
+use super::{
+    common::{self, LineFormat},
+    Codec, DurabilityPolicy, JsonCodec, ReadError, SyncSchedule, WriteError, LOCK_RETRY_INTERVAL,
+};
 use crate::Fact;
 use fs2::FileExt;
+use futures_core::Stream;
 use serde::{de::DeserializeOwned, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
 use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
 use std::time::{Duration, Instant};
 use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::io::{AsyncBufRead, AsyncWriteExt, BufReader, BufWriter};
 
-pub struct AsyncFactStreamWriter {
+/// Result of polling a fact stream reader.
+type PollFact<E, V, S> = Poll<Option<Result<Fact<E, V, S>, ReadError>>>;
+
+/// Poll `reader` for the rest of a line, appending it to `line`.
+///
+/// Completes once the delimiter has been read or the end of the input is
+/// reached, leaving `line` empty only at the end. Bytes are moved into `line`
+/// as they arrive, so nothing is lost when a poll returns `Pending` or the
+/// caller is cancelled: polling again with the same `line` carries on.
+pub(crate) fn poll_read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    cx: &mut Context<'_>,
+    delimiter: u8,
+    line: &mut Vec<u8>,
+) -> Poll<io::Result<()>> {
+    loop {
+        let available = ready!(Pin::new(&mut *reader).poll_fill_buf(cx))?;
+        if available.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        match available.iter().position(|&byte| byte == delimiter) {
+            Some(end) => {
+                line.extend_from_slice(&available[..=end]);
+                Pin::new(&mut *reader).consume(end + 1);
+                return Poll::Ready(Ok(()));
+            }
+            None => {
+                let consumed = available.len();
+                line.extend_from_slice(available);
+                Pin::new(&mut *reader).consume(consumed);
+            }
+        }
+    }
+}
+
+pub struct AsyncFactStreamWriter<C = JsonCodec> {
     sync_file: std::fs::File, // For locking
     writer: BufWriter<File>,
     lock_timeout: Duration,
+    retry_interval: Duration,
+    format: LineFormat,
+    codec: C,
+    schedule: SyncSchedule,
 }
 
 impl AsyncFactStreamWriter {
@@ -44,13 +93,89 @@ impl AsyncFactStreamWriter {
             sync_file,
             writer,
             lock_timeout: timeout,
+            retry_interval: LOCK_RETRY_INTERVAL,
+            format: LineFormat::default(),
+            codec: JsonCodec,
+            schedule: SyncSchedule::new(DurabilityPolicy::default()),
         })
     }
+}
+
+impl<C: Codec> AsyncFactStreamWriter<C> {
+    /// Encode records with `codec` instead of JSON.
+    ///
+    /// See [`FactStreamWriter::with_codec`](crate::FactStreamWriter::with_codec).
+    pub fn with_codec<D: Codec>(self, codec: D) -> AsyncFactStreamWriter<D> {
+        AsyncFactStreamWriter {
+            sync_file: self.sync_file,
+            writer: self.writer,
+            lock_timeout: self.lock_timeout,
+            retry_interval: self.retry_interval,
+            format: self.format,
+            codec,
+            schedule: self.schedule,
+        }
+    }
+
+    /// Write each batch as a single framed record.
+    ///
+    /// See [`FactStreamWriter::with_batch_framing`](crate::FactStreamWriter::with_batch_framing).
+    pub fn with_batch_framing(mut self, enabled: bool) -> Self {
+        self.format.framed = enabled;
+        self
+    }
+
+    /// Append a CRC32C checksum to every line written.
+    ///
+    /// See [`FactStreamWriter::with_checksums`](crate::FactStreamWriter::with_checksums).
+    pub fn with_checksums(mut self, enabled: bool) -> Self {
+        self.format.checksums = enabled;
+        self
+    }
+
+    /// Fsync written batches according to `policy` instead of after each one.
+    ///
+    /// See [`FactStreamWriter::with_durability`](crate::FactStreamWriter::with_durability).
+    pub fn with_durability(mut self, policy: DurabilityPolicy) -> Self {
+        self.schedule = SyncSchedule::new(policy);
+        self
+    }
+
+    pub(crate) fn with_format(mut self, format: LineFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Wait up to `timeout` for the lock, trying every `retry_interval`.
+    pub(crate) fn with_lock_wait(mut self, timeout: Duration, retry_interval: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Carry on with a schedule kept by a store across writers.
+    pub(crate) fn with_schedule(mut self, schedule: SyncSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    pub(crate) fn schedule(&self) -> SyncSchedule {
+        self.schedule
+    }
+
+    /// Force everything written so far to stable storage.
+    ///
+    /// See [`FactStreamWriter::flush_durable`](crate::FactStreamWriter::flush_durable).
+    pub async fn flush_durable(&mut self) -> Result<(), WriteError> {
+        self.writer.flush().await?;
+        self.writer.get_ref().sync_all().await?;
+        self.schedule.synced();
+        Ok(())
+    }
 
     /// Acquire exclusive lock with configured timeout
     async fn acquire_lock(&self) -> Result<(), WriteError> {
         let start = Instant::now();
-        let retry_interval = Duration::from_millis(100);
 
         loop {
             match self.sync_file.try_lock_exclusive() {
@@ -62,7 +187,7 @@ impl AsyncFactStreamWriter {
                     return Err(WriteError::LockTimeout(self.lock_timeout));
                 }
                 Err(_) => {
-                    tokio::time::sleep(retry_interval).await;
+                    tokio::time::sleep(self.retry_interval).await;
                 }
             }
         }
@@ -70,36 +195,71 @@ impl AsyncFactStreamWriter {
 
     /// Write a batch of facts atomically.
     ///
-    /// Acquires exclusive lock, writes all facts, then releases lock.
+    /// Acquires exclusive lock, writes all facts, then releases lock. The
+    /// batch is synced to disk according to the writer's [`DurabilityPolicy`].
     pub async fn write_batch<E, V, S>(&mut self, facts: &[Fact<E, V, S>]) -> Result<(), WriteError>
     where
         E: Serialize,
         V: Serialize,
         S: Serialize,
     {
-        let buffer = common::serialize_batch(facts)?;
+        self.append_batch(facts, async { Ok::<_, WriteError>(()) })
+            .await
+            .map(|_| ())
+    }
+
+    /// Write a batch of facts atomically, returning the byte range it took.
+    ///
+    /// `check` is awaited once the exclusive lock is held and before anything
+    /// is written, so it sees everything other writers have committed; if it
+    /// fails, the batch is not written. The file length is taken while the
+    /// lock is still held, so the range is exactly this batch.
+    pub(crate) async fn append_batch<E, V, S, X>(
+        &mut self,
+        facts: &[Fact<E, V, S>],
+        check: impl Future<Output = Result<(), X>>,
+    ) -> Result<Range<u64>, X>
+    where
+        E: Serialize,
+        V: Serialize,
+        S: Serialize,
+        X: From<WriteError>,
+    {
+        let buffer =
+            common::serialize_batch(&self.codec, facts, self.format).map_err(WriteError::from)?;
 
         // Acquire lock only for the duration of the write
         self.acquire_lock().await?;
 
         let result = async {
-            self.writer.write_all(&buffer).await?;
-            self.writer.flush().await?;
-            self.writer.get_ref().sync_all().await?;
-            Ok(())
+            check.await?;
+            let written = async {
+                let start = self.sync_file.metadata()?.len();
+                self.writer.write_all(&buffer).await?;
+                self.writer.flush().await?;
+                Ok::<_, io::Error>(start..start + buffer.len() as u64)
+            };
+            written.await.map_err(|e| X::from(e.into()))
         }
         .await;
 
         // Always release lock, even on error
         let _ = FileExt::unlock(&self.sync_file);
 
-        result
+        let written = result?;
+        if self.schedule.written(1) {
+            self.flush_durable().await?;
+        }
+        Ok(written)
     }
 }
 
-pub struct AsyncFactStreamReader<E, V, S> {
+pub struct AsyncFactStreamReader<E, V, S, C = JsonCodec> {
     reader: BufReader<File>,
-    _phantom: std::marker::PhantomData<(E, V, S)>,
+    line: Vec<u8>,
+    /// Facts decoded from a framed batch that have not been yielded yet
+    pending: VecDeque<Fact<E, V, S>>,
+    codec: C,
 }
 
 impl<E, V, S> AsyncFactStreamReader<E, V, S> {
@@ -126,7 +286,9 @@ impl<E, V, S> AsyncFactStreamReader<E, V, S> {
 
                     return Ok(Self {
                         reader,
-                        _phantom: std::marker::PhantomData,
+                        line: Vec::new(),
+                        pending: VecDeque::new(),
+                        codec: JsonCodec,
                     });
                 }
                 Err(_) if timeout.is_zero() => {
@@ -141,32 +303,119 @@ impl<E, V, S> AsyncFactStreamReader<E, V, S> {
             }
         }
     }
+}
+
+impl<E, V, S, C: Codec> AsyncFactStreamReader<E, V, S, C> {
+    /// Decode records with `codec` instead of JSON.
+    pub fn with_codec<D: Codec>(self, codec: D) -> AsyncFactStreamReader<E, V, S, D> {
+        AsyncFactStreamReader {
+            reader: self.reader,
+            line: self.line,
+            pending: self.pending,
+            codec,
+        }
+    }
 
+    /// Read the next fact.
+    ///
+    /// Cancellation-safe: a partly read line is kept for the next call.
     pub async fn next(&mut self) -> Option<Result<Fact<E, V, S>, ReadError>>
     where
         E: DeserializeOwned,
         V: DeserializeOwned,
         S: DeserializeOwned,
     {
-        let mut line = String::new();
+        std::future::poll_fn(|cx| self.poll_next_fact(cx)).await
+    }
 
+    fn poll_next_fact(&mut self, cx: &mut Context<'_>) -> PollFact<E, V, S>
+    where
+        E: DeserializeOwned,
+        V: DeserializeOwned,
+        S: DeserializeOwned,
+    {
         loop {
-            line.clear();
-            match self.reader.read_line(&mut line).await {
-                Ok(0) => return None,
-                Ok(_) => {
-                    let trimmed = line.trim();
-                    if trimmed.is_empty() {
-                        continue;
-                    }
-
-                    match serde_json::from_str(trimmed) {
-                        Ok(fact) => return Some(Ok(fact)),
-                        Err(e) => return Some(Err(ReadError::Deserialization(e))),
-                    }
+            if let Some(fact) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(fact)));
+            }
+
+            if let Err(e) = ready!(poll_read_line(
+                &mut self.reader,
+                cx,
+                C::DELIMITER,
+                &mut self.line
+            )) {
+                return Poll::Ready(Some(Err(ReadError::Io(e))));
+            }
+            if self.line.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            // The line is complete, so start the next one afresh
+            let decoded = if common::is_blank(&self.line) {
+                Ok(())
+            } else {
+                common::decode_line(&self.codec, &self.line, &mut self.pending)
+            };
+            let terminated = self.line.last() == Some(&C::DELIMITER);
+            self.line.clear();
+
+            if let Err(e) = decoded {
+                if !terminated {
+                    return Poll::Ready(None); // A write still in progress
                 }
-                Err(e) => return Some(Err(ReadError::Io(e))),
+                return Poll::Ready(Some(Err(e)));
             }
         }
     }
 }
+
+// Nothing is pinned structurally: the reader is only ever polled by reference
+impl<E, V, S, C> Unpin for AsyncFactStreamReader<E, V, S, C> {}
+
+impl<E, V, S, C> Stream for AsyncFactStreamReader<E, V, S, C>
+where
+    C: Codec,
+    E: DeserializeOwned,
+    V: DeserializeOwned,
+    S: DeserializeOwned,
+{
+    type Item = Result<Fact<E, V, S>, ReadError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_fact(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Operation;
+    use futures::StreamExt;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn reader_is_a_stream() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts: Vec<Fact<String, u32, String>> = (0..5)
+            .map(|i| {
+                Fact::new(
+                    format!("item{i}"),
+                    i,
+                    "2024-01-15T10:00:00Z".parse().unwrap(),
+                    "source1".to_string(),
+                    Operation::Assert,
+                )
+            })
+            .collect();
+        let mut writer = AsyncFactStreamWriter::open(temp.path()).await.unwrap();
+        writer.write_batch(&facts).await.unwrap();
+        // A write still in progress is not part of the stream
+        writer.writer.write_all(b"[\"item5\",").await.unwrap();
+        writer.writer.flush().await.unwrap();
+
+        let reader = AsyncFactStreamReader::open(temp.path()).await.unwrap();
+        let read: Vec<_> = reader.map(Result::unwrap).collect().await;
+        assert_eq!(read, facts);
+    }
+}
@@ -0,0 +1,190 @@
+// stainless_facts/src/io/codec.rs
+//
+// Pluggable on-disk encodings for fact streams.
+
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+/// Failure to encode or decode a record.
+#[derive(Debug, Error)]
+pub enum CodecError {
+    #[error("JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Checksum mismatch: record says {expected:08x}, contents hash to {actual:08x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+
+    #[error("Malformed record: {0}")]
+    Malformed(String),
+
+    #[error("{0}")]
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl CodecError {
+    /// The underlying `serde_json` error, if the JSON codec failed.
+    pub fn as_json(&self) -> Option<&serde_json::Error> {
+        match self {
+            CodecError::Json(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// An on-disk encoding for fact streams.
+///
+/// A stream is a sequence of records, each holding a single fact or a framed
+/// batch of facts and ending with [`Codec::DELIMITER`]. Because the delimiter
+/// never occurs inside a record, record boundaries can be found from any
+/// byte offset, which is what seeking, tail reads and crash recovery rely on.
+///
+/// Facts are serialized with serde. The format must be self-describing: a
+/// record is decoded without knowing up front whether it holds a single fact
+/// or a batch, and timestamps are decoded without knowing the fact's types.
+pub trait Codec: Clone + Send + Sync + 'static {
+    /// Byte that ends every record and never occurs inside one.
+    const DELIMITER: u8;
+
+    /// Whether facts are stored in the compact layout: the timestamp as
+    /// integer nanoseconds since the Unix epoch and the operation as a single
+    /// byte, instead of the serde representation of [`Fact`](crate::Fact).
+    ///
+    /// Only facts written through a codec that sets this are affected. The
+    /// compact layout cannot hold timestamps before 1677 or after 2262.
+    const COMPACT_FACTS: bool = false;
+
+    /// Append `value` to `out` as one record, including the delimiter.
+    ///
+    /// With `checksum`, the record also carries a checksum of its contents
+    /// that [`Codec::decode`] verifies.
+    fn encode<T: Serialize>(
+        &self,
+        value: &T,
+        checksum: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CodecError>;
+
+    /// Decode a record, given without its delimiter.
+    ///
+    /// Records that carry a checksum must match it; records without one are
+    /// decoded as they are.
+    fn decode<T: DeserializeOwned>(&self, record: &[u8]) -> Result<T, CodecError>;
+
+    /// Whether a record, given without its delimiter, carries a checksum.
+    fn has_checksum(&self, record: &[u8]) -> bool;
+}
+
+/// Newline-delimited JSON, the default encoding.
+///
+/// A checksum is written after the JSON as a tab and eight hex digits.
+/// serde_json never writes raw tabs, so it cannot be mistaken for a fact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    const DELIMITER: u8 = b'\n';
+
+    fn encode<T: Serialize>(
+        &self,
+        value: &T,
+        checksum: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        let start = out.len();
+        serde_json::to_writer(&mut *out, value)?;
+        if checksum {
+            let checksum = crc32c::crc32c(&out[start..]);
+            out.extend_from_slice(format!("\t{checksum:08x}").as_bytes());
+        }
+        out.push(Self::DELIMITER);
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, record: &[u8]) -> Result<T, CodecError> {
+        let payload = match split_checksum(record) {
+            (payload, Some(expected)) => {
+                let actual = crc32c::crc32c(payload);
+                if actual != expected {
+                    return Err(CodecError::ChecksumMismatch { expected, actual });
+                }
+                payload
+            }
+            (payload, None) => payload,
+        };
+        Ok(serde_json::from_slice(payload)?)
+    }
+
+    fn has_checksum(&self, record: &[u8]) -> bool {
+        split_checksum(record).1.is_some()
+    }
+}
+
+/// Split a JSON record into its payload and checksum, if it has one.
+fn split_checksum(record: &[u8]) -> (&[u8], Option<u32>) {
+    let end = record
+        .iter()
+        .rposition(|b| !matches!(b, b'\n' | b'\r'))
+        .map_or(0, |i| i + 1);
+    let record = &record[..end];
+
+    let checksum = end.checked_sub(9).and_then(|split| {
+        let (payload, suffix) = record.split_at(split);
+        let digits = suffix.strip_prefix(b"\t")?;
+        if !digits.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        let digits = std::str::from_utf8(digits).ok()?;
+        Some((payload, u32::from_str_radix(digits, 16).ok()?))
+    });
+
+    match checksum {
+        Some((payload, checksum)) => (payload, Some(checksum)),
+        None => (record, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_checksum_round_trips() {
+        let mut record = Vec::new();
+        JsonCodec.encode(&[1, 2, 3], true, &mut record).unwrap();
+        record.pop();
+
+        assert!(JsonCodec.has_checksum(&record));
+        assert_eq!(JsonCodec.decode::<Vec<u32>>(&record).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn json_checksum_mismatch_is_reported() {
+        let mut record = Vec::new();
+        JsonCodec.encode(&[1, 2, 3], true, &mut record).unwrap();
+        record[1] = b'7';
+
+        let result = JsonCodec.decode::<Vec<u32>>(&record);
+
+        assert!(matches!(result, Err(CodecError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn json_failures_expose_the_serde_json_error() {
+        let result = JsonCodec.decode::<Vec<u32>>(b"[1,");
+
+        let error = result.unwrap_err();
+        assert!(error.as_json().is_some_and(|e| e.is_eof()));
+    }
+
+    #[test]
+    fn json_without_checksum_is_accepted() {
+        let mut record = Vec::new();
+        JsonCodec.encode(&[1, 2, 3], false, &mut record).unwrap();
+
+        let (payload, checksum) = split_checksum(&record);
+
+        assert_eq!(checksum, None);
+        assert_eq!(payload, b"[1,2,3]");
+        assert_eq!(JsonCodec.decode::<Vec<u32>>(&record).unwrap(), [1, 2, 3]);
+    }
+}
@@ -1,33 +1,494 @@
-use crate::Fact;
-use serde::Serialize;
+use super::{
+    compression::{self, StreamFile},
+    Codec, CodecError, ReadError,
+};
+use crate::{Fact, Operation};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{
+    de::{
+        self,
+        value::{MapAccessDeserializer, SeqAccessDeserializer},
+        DeserializeOwned, IgnoredAny, MapAccess, SeqAccess, Visitor,
+    },
+    ser, Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// Bytes read per step when scanning backwards from the end of a stream.
+const TAIL_CHUNK: u64 = 8192;
+
+/// How facts are laid out on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LineFormat {
+    /// Write each batch as a single framed line
+    pub(crate) framed: bool,
+    /// Append a CRC32C checksum to every line
+    pub(crate) checksums: bool,
+}
 
 /// Serialize a batch of facts to a buffer.
 ///
-/// All facts are serialized as newline-delimited JSON. Newlines within
-/// fact values are automatically escaped by serde_json.
-pub(crate) fn serialize_batch<E, V, S>(
+/// Facts are written one record per fact, or one framed batch per record.
+/// With the JSON codec a record is a line, and newlines within fact values
+/// are automatically escaped by serde_json.
+pub(crate) fn serialize_batch<C, E, V, S>(
+    codec: &C,
     facts: &[Fact<E, V, S>],
-) -> Result<Vec<u8>, serde_json::Error>
+    format: LineFormat,
+) -> Result<Vec<u8>, CodecError>
 where
+    C: Codec,
     E: Serialize,
     V: Serialize,
     S: Serialize,
 {
     let mut buffer = Vec::new();
 
-    for fact in facts {
-        serde_json::to_writer(&mut buffer, fact)?;
-        buffer.push(b'\n');
+    if format.framed {
+        if !facts.is_empty() {
+            let batch = StoredFacts::<C, _, _, _>(facts, PhantomData);
+            codec.encode(&BatchFrame { batch }, format.checksums, &mut buffer)?;
+        }
+    } else {
+        for fact in facts {
+            let fact = StoredFact::<C, _, _, _>(fact, PhantomData);
+            codec.encode(&fact, format.checksums, &mut buffer)?;
+        }
     }
 
     Ok(buffer)
 }
 
+/// A whole batch of facts stored in a single record.
+///
+/// Readers only act on complete lines, so a framed batch becomes visible all
+/// at once: until its terminating newline is written the line does not
+/// parse, and a write that never finishes leaves a torn line that recovery
+/// removes. Facts are sequences and frames are maps, so the two never
+/// collide.
+#[derive(Serialize, Deserialize)]
+struct BatchFrame<T> {
+    batch: T,
+}
+
+/// The contents of a record: a single `T` or a framed batch of them.
+enum Record<T> {
+    Single(T),
+    Batch(Vec<T>),
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Record<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(RecordVisitor(PhantomData))
+    }
+}
+
+struct RecordVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for RecordVisitor<T> {
+    type Value = Record<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a fact or a framed batch of facts")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        T::deserialize(SeqAccessDeserializer::new(seq)).map(Record::Single)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        BatchFrame::deserialize(MapAccessDeserializer::new(map))
+            .map(|frame| Record::Batch(frame.batch))
+    }
+}
+
+/// Strip the delimiter from the end of a record, if it is there.
+fn strip_delimiter<C: Codec>(line: &[u8]) -> &[u8] {
+    line.strip_suffix(&[C::DELIMITER]).unwrap_or(line)
+}
+
+/// Decode a line holding either a single fact or a framed batch.
+pub(crate) fn decode_line<C, E, V, S>(
+    codec: &C,
+    line: &[u8],
+    facts: &mut VecDeque<Fact<E, V, S>>,
+) -> Result<(), ReadError>
+where
+    C: Codec,
+    E: DeserializeOwned,
+    V: DeserializeOwned,
+    S: DeserializeOwned,
+{
+    match codec.decode(strip_delimiter::<C>(line))? {
+        Record::Single(LoadedFact::<C, _, _, _>(fact, _)) => facts.push_back(fact),
+        Record::Batch(batch) => facts.extend(batch.into_iter().map(|loaded| loaded.0)),
+    }
+    Ok(())
+}
+
+/// Whether a line carries a checksum.
+pub(crate) fn has_checksum<C: Codec>(codec: &C, line: &[u8]) -> bool {
+    codec.has_checksum(strip_delimiter::<C>(line))
+}
+
+/// Reads delimiter-terminated records together with their byte offsets.
+///
+/// A trailing record without a terminating delimiter is treated as torn and
+/// is never returned.
+pub(crate) struct LineScanner<R> {
+    reader: R,
+    delimiter: u8,
+    offset: u64,
+    line: Vec<u8>,
+}
+
+impl<R: BufRead> LineScanner<R> {
+    /// Create a scanner whose reader is positioned at `offset`.
+    pub(crate) fn new(reader: R, delimiter: u8, offset: u64) -> Self {
+        Self {
+            reader,
+            delimiter,
+            offset,
+            line: Vec::with_capacity(1024),
+        }
+    }
+
+    /// Byte offset just past the last complete line returned.
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Read the next complete line, returning its starting offset.
+    pub(crate) fn next_line(&mut self) -> io::Result<Option<(u64, &[u8])>> {
+        self.line.clear();
+        let read = self.reader.read_until(self.delimiter, &mut self.line)?;
+        if read == 0 || self.line.last() != Some(&self.delimiter) {
+            return Ok(None);
+        }
+
+        let start = self.offset;
+        self.offset += read as u64;
+        Ok(Some((start, &self.line)))
+    }
+}
+
+/// Find the last complete, non-blank line by reading backwards from the end.
+///
+/// Bytes after the final delimiter belong to a torn write and are ignored,
+/// as are trailing blank lines. Returns the line's offset and contents
+/// without the delimiter, or `None` if the stream holds no complete line.
+pub(crate) fn read_last_line<R: Read + Seek>(
+    reader: &mut R,
+    delimiter: u8,
+) -> io::Result<Option<(u64, Vec<u8>)>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+
+    // `buffer` holds the bytes from `start` to the end of the stream
+    let mut buffer = Vec::new();
+    let mut start = len;
+    let mut chunk = TAIL_CHUNK;
+    // Newline terminating the candidate line, once the last newline is found
+    let mut line_end: Option<u64> = None;
+    // Only `buffer[..unsearched]` may still contain newlines of interest
+    let mut unsearched = 0;
+
+    loop {
+        if let Some(i) = buffer[..unsearched].iter().rposition(|&b| b == delimiter) {
+            if let Some(end) = line_end {
+                let line = &buffer[i + 1..(end - start) as usize];
+                if !is_blank(line) {
+                    return Ok(Some((start + i as u64 + 1, line.to_vec())));
+                }
+            }
+            line_end = Some(start + i as u64);
+            unsearched = i;
+            continue;
+        }
+
+        if start == 0 {
+            return Ok(line_end
+                .map(|end| &buffer[..end as usize])
+                .filter(|line| !is_blank(line))
+                .map(|line| (0, line.to_vec())));
+        }
+
+        // Prepend the preceding chunk, doubling the step for long lines
+        let step = chunk.min(start);
+        start -= step;
+        reader.seek(SeekFrom::Start(start))?;
+        let mut preceding = vec![0; step as usize];
+        reader.read_exact(&mut preceding)?;
+        preceding.extend_from_slice(&buffer);
+        buffer = preceding;
+        unsearched = step as usize;
+        chunk *= 2;
+    }
+}
+
+/// Length of the stream up to and including its last delimiter.
+///
+/// Anything after that belongs to a record whose write never completed.
+pub(crate) fn complete_len<R: Read + Seek>(reader: &mut R, delimiter: u8) -> io::Result<u64> {
+    let mut end = reader.seek(SeekFrom::End(0))?;
+    let mut chunk = vec![0; TAIL_CHUNK as usize];
+
+    while end > 0 {
+        let step = TAIL_CHUNK.min(end);
+        let start = end - step;
+        reader.seek(SeekFrom::Start(start))?;
+        let buffer = &mut chunk[..step as usize];
+        reader.read_exact(buffer)?;
+
+        if let Some(i) = buffer.iter().rposition(|&b| b == delimiter) {
+            return Ok(start + i as u64 + 1);
+        }
+        end = start;
+    }
+
+    Ok(0)
+}
+
+/// One-based number of the line that starts at byte `offset`.
+pub(crate) fn line_number(path: &Path, offset: u64, delimiter: u8) -> io::Result<u64> {
+    let mut reader = BufReader::new(StreamFile::open(path)?.take(offset));
+    let mut newlines = 0;
+
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(newlines + 1);
+        }
+        newlines += buffer.iter().filter(|&&b| b == delimiter).count() as u64;
+        let consumed = buffer.len();
+        reader.consume(consumed);
+    }
+}
+
+pub(crate) fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Decode the first and last timestamp on a line.
+///
+/// Only the timestamp column is decoded; entity, value and source are
+/// skipped without being materialized, so this works for any fact stream
+/// regardless of its concrete types. For a single fact both timestamps are
+/// the same; for a framed batch they bound the facts in it. Lines that fail
+/// their checksum are rejected.
+pub(crate) fn parse_timestamps<C: Codec>(
+    codec: &C,
+    line: &[u8],
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    match codec.decode(strip_delimiter::<C>(line)).ok()? {
+        Record::Single(TimestampColumn::<C>(timestamp, _)) => Some((timestamp, timestamp)),
+        Record::Batch(batch) => Some((batch.first()?.0, batch.last()?.0)),
+    }
+}
+
+/// Number of facts on a line: one, or the size of a framed batch.
+///
+/// Like [`parse_timestamps`], the facts are skipped without being
+/// materialized. Returns `None` for lines that cannot be decoded.
+pub(crate) fn facts_on_line<C: Codec>(codec: &C, line: &[u8]) -> Option<usize> {
+    match codec.decode(strip_delimiter::<C>(line)).ok()? {
+        Record::<IgnoredAny>::Single(_) => Some(1),
+        Record::Batch(batch) => Some(batch.len()),
+    }
+}
+
+/// Length of the stream file at `path` with no append half way through, or
+/// 0 if it does not exist yet.
+///
+/// Measured under a shared lock, so it waits for a writer holding the
+/// exclusive one to finish. `None` for a compressed segment, which is sealed
+/// and never grows.
+pub(crate) fn committed_len(path: &Path) -> io::Result<Option<u64>> {
+    let mut file = match compression::open_segment(path) {
+        Ok((_, file)) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(0)),
+        Err(e) => return Err(e),
+    };
+    let Some(plain) = file.as_plain_mut() else {
+        return Ok(None);
+    };
+    FileExt::lock_shared(plain)?;
+    let len = plain.metadata().map(|metadata| metadata.len());
+    let _ = FileExt::unlock(plain);
+    len.map(Some)
+}
+
+/// Take a shared lock on the stream file at `path`, waiting for a writer
+/// holding the exclusive one to finish. `None` if the file does not exist.
+///
+/// The lock is held until the returned file is dropped.
+pub(crate) fn lock_shared(path: &Path) -> io::Result<Option<File>> {
+    match File::open(path) {
+        Ok(file) => FileExt::lock_shared(&file).map(|()| Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Count the facts on the complete lines of the stream at `path` in the
+/// byte range `from..to`.
+///
+/// Both ends must be line boundaries. Lines that cannot be decoded hold no
+/// facts, and a missing file holds none at all. A segment that was replaced
+/// by its compressed copy is read from the copy.
+pub(crate) fn count_facts<C: Codec>(codec: &C, path: &Path, from: u64, to: u64) -> io::Result<u64> {
+    if to <= from {
+        return Ok(0);
+    }
+    let mut file = match compression::open_segment(path) {
+        Ok((_, file)) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    // A compressed stream cannot seek, so it is read up to `from`
+    match file.as_plain_mut() {
+        Some(plain) => {
+            plain.seek(SeekFrom::Start(from))?;
+        }
+        None => {
+            io::copy(&mut (&mut file).take(from), &mut io::sink())?;
+        }
+    }
+
+    let reader = BufReader::new(file.take(to - from));
+    let mut scanner = LineScanner::new(reader, C::DELIMITER, from);
+    let mut facts = 0;
+    while let Some((_, line)) = scanner.next_line()? {
+        facts += facts_on_line(codec, line).unwrap_or(0) as u64;
+    }
+    Ok(facts)
+}
+
+/// A fact in the layout its codec stores it in.
+struct StoredFact<'a, C, E, V, S>(&'a Fact<E, V, S>, PhantomData<C>);
+
+impl<C, E, V, S> Serialize for StoredFact<'_, C, E, V, S>
+where
+    C: Codec,
+    E: Serialize,
+    V: Serialize,
+    S: Serialize,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let fact = self.0;
+        if !C::COMPACT_FACTS {
+            return fact.serialize(serializer);
+        }
+        let nanos = fact.timestamp().timestamp_nanos_opt().ok_or_else(|| {
+            ser::Error::custom("timestamp is outside the range of nanosecond precision")
+        })?;
+        let operation = match fact.operation() {
+            Operation::Assert => ASSERT,
+            Operation::Retract => RETRACT,
+        };
+        (fact.entity(), fact.value(), nanos, fact.source(), operation).serialize(serializer)
+    }
+}
+
+/// A framed batch of facts in the layout their codec stores them in.
+struct StoredFacts<'a, C, E, V, S>(&'a [Fact<E, V, S>], PhantomData<C>);
+
+impl<C, E, V, S> Serialize for StoredFacts<'_, C, E, V, S>
+where
+    C: Codec,
+    E: Serialize,
+    V: Serialize,
+    S: Serialize,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        serializer.collect_seq(
+            self.0
+                .iter()
+                .map(|fact| StoredFact::<C, _, _, _>(fact, PhantomData)),
+        )
+    }
+}
+
+/// Operation bytes of the compact layout.
+const ASSERT: u8 = 0;
+const RETRACT: u8 = 1;
+
+/// A fact decoded from the layout its codec stores it in.
+struct LoadedFact<C, E, V, S>(Fact<E, V, S>, PhantomData<C>);
+
+impl<'de, C, E, V, S> Deserialize<'de> for LoadedFact<C, E, V, S>
+where
+    C: Codec,
+    E: Deserialize<'de>,
+    V: Deserialize<'de>,
+    S: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !C::COMPACT_FACTS {
+            return Fact::deserialize(deserializer).map(|fact| Self(fact, PhantomData));
+        }
+        let (entity, value, nanos, source, operation) =
+            <(E, V, i64, S, u8)>::deserialize(deserializer)?;
+        let operation = compact_operation(operation)?;
+        let timestamp = DateTime::from_timestamp_nanos(nanos);
+        Ok(Self(
+            Fact::new(entity, value, timestamp, source, operation),
+            PhantomData,
+        ))
+    }
+}
+
+fn compact_operation<E: de::Error>(byte: u8) -> Result<Operation, E> {
+    match byte {
+        ASSERT => Ok(Operation::Assert),
+        RETRACT => Ok(Operation::Retract),
+        other => Err(E::invalid_value(
+            de::Unexpected::Unsigned(other.into()),
+            &"0 (Assert) or 1 (Retract)",
+        )),
+    }
+}
+
+/// The timestamp of a fact, decoded without its other columns.
+struct TimestampColumn<C>(DateTime<Utc>, PhantomData<C>);
+
+impl<'de, C: Codec> Deserialize<'de> for TimestampColumn<C> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let timestamp = if C::COMPACT_FACTS {
+            type Columns = (IgnoredAny, IgnoredAny, i64, IgnoredAny, IgnoredAny);
+            let (_, _, nanos, _, _) = Columns::deserialize(deserializer)?;
+            DateTime::from_timestamp_nanos(nanos)
+        } else {
+            type Columns = (
+                IgnoredAny,
+                IgnoredAny,
+                DateTime<Utc>,
+                IgnoredAny,
+                IgnoredAny,
+            );
+            let (_, _, timestamp, _, _) = Columns::deserialize(deserializer)?;
+            timestamp
+        };
+        Ok(Self(timestamp, PhantomData))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::io::JsonCodec;
     use crate::Operation;
 
+    const FRAMED: LineFormat = LineFormat {
+        framed: true,
+        checksums: false,
+    };
+
     #[test]
     fn newlines_in_values_are_escaped() {
         let fact = Fact::new(
@@ -38,11 +499,197 @@ mod tests {
             Operation::Assert,
         );
 
-        let buffer = serialize_batch(&[fact]).unwrap();
+        let buffer = serialize_batch(&JsonCodec, &[fact], LineFormat::default()).unwrap();
         let text = String::from_utf8(buffer).unwrap();
 
         // Should be exactly one newline (the delimiter)
         assert_eq!(text.matches('\n').count(), 1);
         assert!(text.contains("\\n"));
     }
+
+    fn fact_at(minute: u32) -> Fact<String, serde_json::Value, String> {
+        Fact::new(
+            "track1".to_string(),
+            serde_json::json!({"t": "Whatever", "v": [1, 2, 3]}),
+            format!("2024-01-15T10:{minute:02}:00Z").parse().unwrap(),
+            "alice".to_string(),
+            Operation::Assert,
+        )
+    }
+
+    #[test]
+    fn parse_timestamps_reads_only_timestamp_column() {
+        let fact = fact_at(0);
+
+        let buffer = serialize_batch(
+            &JsonCodec,
+            std::slice::from_ref(&fact),
+            LineFormat::default(),
+        )
+        .unwrap();
+
+        let timestamp = *fact.timestamp();
+        assert_eq!(
+            parse_timestamps(&JsonCodec, &buffer),
+            Some((timestamp, timestamp))
+        );
+    }
+
+    #[test]
+    fn parse_timestamps_of_framed_batch_spans_batch() {
+        let facts = [fact_at(0), fact_at(1), fact_at(2)];
+
+        let buffer = serialize_batch(&JsonCodec, &facts, FRAMED).unwrap();
+
+        let bounds = (*facts[0].timestamp(), *facts[2].timestamp());
+        assert_eq!(parse_timestamps(&JsonCodec, &buffer), Some(bounds));
+    }
+
+    #[test]
+    fn parse_timestamps_rejects_garbage() {
+        assert_eq!(
+            parse_timestamps(&JsonCodec, b"[\"track1\",{\"t\":\"Bpm\""),
+            None
+        );
+        assert_eq!(
+            parse_timestamps(&JsonCodec, b"{\"batch\":[[\"track1\""),
+            None
+        );
+    }
+
+    #[test]
+    fn facts_on_line_counts_framed_batches() {
+        let facts = [fact_at(0), fact_at(1), fact_at(2)];
+        let single = serialize_batch(&JsonCodec, &facts[..1], LineFormat::default()).unwrap();
+        let framed = serialize_batch(&JsonCodec, &facts, FRAMED).unwrap();
+
+        assert_eq!(facts_on_line(&JsonCodec, &single), Some(1));
+        assert_eq!(facts_on_line(&JsonCodec, &framed), Some(3));
+        assert_eq!(facts_on_line(&JsonCodec, b"{\"batch\":[[\"track1\""), None);
+    }
+
+    #[test]
+    fn framed_batch_is_a_single_line() {
+        let facts = [fact_at(0), fact_at(1)];
+
+        let buffer = serialize_batch(&JsonCodec, &facts, FRAMED).unwrap();
+
+        assert_eq!(buffer.iter().filter(|&&b| b == b'\n').count(), 1);
+        assert_eq!(buffer.last(), Some(&b'\n'));
+    }
+
+    #[test]
+    fn decode_line_reads_single_facts_and_framed_batches() {
+        let facts = [fact_at(0), fact_at(1), fact_at(2)];
+        let single = serialize_batch(&JsonCodec, &facts[..1], LineFormat::default()).unwrap();
+        let framed = serialize_batch(&JsonCodec, &facts[1..], FRAMED).unwrap();
+
+        let mut decoded = VecDeque::new();
+        decode_line(&JsonCodec, &single, &mut decoded).unwrap();
+        decode_line(&JsonCodec, &framed, &mut decoded).unwrap();
+
+        assert_eq!(decoded, facts);
+    }
+
+    #[test]
+    fn checksummed_lines_round_trip() {
+        let facts = [fact_at(0), fact_at(1)];
+        let format = LineFormat {
+            framed: false,
+            checksums: true,
+        };
+
+        let buffer = serialize_batch(&JsonCodec, &facts, format).unwrap();
+
+        let mut decoded = VecDeque::new();
+        for line in buffer.split_inclusive(|&b| b == b'\n') {
+            assert!(has_checksum(&JsonCodec, line));
+            decode_line(&JsonCodec, line, &mut decoded).unwrap();
+        }
+        assert_eq!(decoded, facts);
+    }
+
+    #[test]
+    fn checksum_mismatch_is_reported() {
+        let format = LineFormat {
+            framed: true,
+            checksums: true,
+        };
+        let mut buffer = serialize_batch(&JsonCodec, &[fact_at(0)], format).unwrap();
+        // Flip a digit of the minute without breaking the JSON
+        let position = buffer.windows(5).position(|w| w == b"10:00").unwrap();
+        buffer[position + 4] = b'9';
+
+        let mut decoded = VecDeque::<Fact<String, serde_json::Value, String>>::new();
+        let result = decode_line(&JsonCodec, &buffer, &mut decoded);
+
+        assert!(matches!(result, Err(ReadError::ChecksumMismatch { .. })));
+        assert!(decoded.is_empty());
+        assert_eq!(parse_timestamps(&JsonCodec, &buffer), None);
+    }
+
+    #[test]
+    fn complete_len_excludes_torn_trailing_line() {
+        let mut stream = io::Cursor::new(b"[1]\n[2]\n[3".to_vec());
+
+        assert_eq!(complete_len(&mut stream, b'\n').unwrap(), 8);
+    }
+
+    #[test]
+    fn complete_len_without_any_newline_is_zero() {
+        let mut stream = io::Cursor::new(vec![b'x'; 2 * TAIL_CHUNK as usize]);
+
+        assert_eq!(complete_len(&mut stream, b'\n').unwrap(), 0);
+    }
+
+    #[test]
+    fn read_last_line_ignores_torn_trailing_line() {
+        let mut stream = io::Cursor::new(b"[1]\n[2]\n[3".to_vec());
+
+        let last = read_last_line(&mut stream, b'\n').unwrap();
+
+        assert_eq!(last, Some((4, b"[2]".to_vec())));
+    }
+
+    #[test]
+    fn read_last_line_skips_trailing_blank_lines() {
+        let mut stream = io::Cursor::new(b"[1]\n[2]\n\n  \n".to_vec());
+
+        let last = read_last_line(&mut stream, b'\n').unwrap();
+
+        assert_eq!(last, Some((4, b"[2]".to_vec())));
+    }
+
+    #[test]
+    fn read_last_line_finds_single_line() {
+        let mut stream = io::Cursor::new(b"[1]\n".to_vec());
+
+        let last = read_last_line(&mut stream, b'\n').unwrap();
+
+        assert_eq!(last, Some((0, b"[1]".to_vec())));
+    }
+
+    #[test]
+    fn read_last_line_of_empty_or_torn_stream_is_none() {
+        assert_eq!(
+            read_last_line(&mut io::Cursor::new(Vec::new()), b'\n').unwrap(),
+            None
+        );
+        assert_eq!(
+            read_last_line(&mut io::Cursor::new(b"[1".to_vec()), b'\n').unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn read_last_line_spans_several_chunks() {
+        let long_line = vec![b'x'; 3 * TAIL_CHUNK as usize];
+        let mut contents = b"[1]\n".to_vec();
+        contents.extend_from_slice(&long_line);
+        contents.extend_from_slice(b"\n[2");
+
+        let last = read_last_line(&mut io::Cursor::new(contents), b'\n').unwrap();
+
+        assert_eq!(last, Some((4, long_line)));
+    }
 }
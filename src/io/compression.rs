@@ -0,0 +1,193 @@
+// stainless_facts/src/io/compression.rs
+//
+// Transparent decompression of compressed stream files.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Magic number at the start of every zstd frame.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Extension appended to the name of a compressed segment.
+#[cfg(feature = "compression")]
+pub(crate) const COMPRESSED_EXTENSION: &str = "zst";
+
+/// A stream file opened for reading, decompressed on the fly if needed.
+///
+/// Compressed files are detected by their contents, not their name, so a
+/// compressed stream can be read under any file name.
+pub(crate) enum StreamFile {
+    Plain(File),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::stream::read::Decoder<'static, io::BufReader<File>>),
+}
+
+impl StreamFile {
+    /// Open the stream file at `path`.
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Read from `file`, which must be positioned at its start.
+    ///
+    /// Without the `compression` feature, a compressed file is an error
+    /// rather than a stream of unparseable lines.
+    pub(crate) fn from_file(mut file: File) -> io::Result<Self> {
+        if !is_compressed(&mut file)? {
+            return Ok(Self::Plain(file));
+        }
+
+        #[cfg(feature = "compression")]
+        {
+            Ok(Self::Zstd(zstd::stream::read::Decoder::new(file)?))
+        }
+        #[cfg(not(feature = "compression"))]
+        {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream is zstd-compressed; enable the `compression` feature to read it",
+            ))
+        }
+    }
+
+    /// The file itself, unless it is compressed and so cannot be seeked.
+    pub(crate) fn as_plain_mut(&mut self) -> Option<&mut File> {
+        match self {
+            Self::Plain(file) => Some(file),
+            #[cfg(feature = "compression")]
+            Self::Zstd(_) => None,
+        }
+    }
+}
+
+/// Only plain files can be seeked.
+impl Seek for StreamFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Plain(file) => file.seek(position),
+            #[cfg(feature = "compression")]
+            Self::Zstd(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "compressed streams cannot be seeked",
+            )),
+        }
+    }
+}
+
+impl Read for StreamFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.read(buf),
+            #[cfg(feature = "compression")]
+            Self::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// Open a segment file, or the compressed file it was replaced by.
+///
+/// Segments are compressed after they are sealed, so a reader that listed
+/// the plain file may find the compressed one in its place. Returns the
+/// path that was actually opened.
+pub(crate) fn open_segment(path: &Path) -> io::Result<(PathBuf, StreamFile)> {
+    match StreamFile::open(path) {
+        #[cfg(feature = "compression")]
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let compressed = compressed_path(path);
+            match StreamFile::open(&compressed) {
+                Ok(file) => Ok((compressed, file)),
+                Err(_) => Err(e),
+            }
+        }
+        result => result.map(|file| (path.to_path_buf(), file)),
+    }
+}
+
+/// Whether `file` holds zstd-compressed data. Leaves it at its start.
+pub(crate) fn is_compressed(file: &mut File) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+    file.by_ref()
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(magic == ZSTD_MAGIC)
+}
+
+/// Path of the compressed counterpart of the segment at `path`.
+#[cfg(feature = "compression")]
+pub(crate) fn compressed_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(COMPRESSED_EXTENSION);
+    PathBuf::from(name)
+}
+
+/// Write a zstd-compressed copy of the file at `path` next to it.
+///
+/// The copy is written under a temporary name and renamed into place once
+/// it is on disk, so it is never seen half written. The temporary name is
+/// unique to the process, so processes compressing the same file do not
+/// write over each other. The original is left for the caller to remove.
+/// Returns the path of the copy.
+#[cfg(feature = "compression")]
+pub(crate) fn compress_file(path: &Path, level: i32) -> io::Result<PathBuf> {
+    let compressed = compressed_path(path);
+    let mut temporary = compressed.clone().into_os_string();
+    temporary.push(format!(".{}.tmp", std::process::id()));
+    let temporary = PathBuf::from(temporary);
+
+    let mut source = File::open(path)?;
+    let mut encoder = zstd::stream::write::Encoder::new(File::create(&temporary)?, level)?;
+    io::copy(&mut source, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+
+    std::fs::rename(&temporary, &compressed)?;
+    Ok(compressed)
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+    use crate::io::{FactStreamReader, FactStreamWriter};
+    use crate::testing::item_facts;
+    use crate::Fact;
+    use tempfile::TempDir;
+
+    #[test]
+    fn reader_decompresses_transparently() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let facts = item_facts(0..100);
+        FactStreamWriter::open(&path)
+            .unwrap()
+            .write_batch(&facts)
+            .unwrap();
+
+        let compressed = compress_file(&path, 3).unwrap();
+        assert!(
+            std::fs::metadata(&compressed).unwrap().len() < std::fs::metadata(&path).unwrap().len()
+        );
+
+        let read: Result<Vec<Fact<String, i64, String>>, _> =
+            FactStreamReader::open(&compressed).unwrap().collect();
+        assert_eq!(read.unwrap(), facts);
+    }
+
+    #[test]
+    fn open_segment_falls_back_to_compressed_copy() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("0000000000.facts");
+        std::fs::write(&path, b"[1]\n").unwrap();
+        let compressed = compress_file(&path, 3).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (opened, mut file) = open_segment(&path).unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+
+        assert_eq!(opened, compressed);
+        assert_eq!(contents, "[1]\n");
+        assert!(file.as_plain_mut().is_none());
+    }
+}
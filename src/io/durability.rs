@@ -0,0 +1,119 @@
+// stainless_facts/src/io/durability.rs
+//
+// When written batches are forced to stable storage.
+
+use std::time::{Duration, Instant};
+
+/// When a writer forces the batches it wrote to stable storage with fsync.
+///
+/// Every batch is handed to the operating system as soon as it is written,
+/// whatever the policy, so a crash of the writing process alone loses
+/// nothing that was acknowledged. The policy decides what an operating
+/// system crash or power loss can take with it: batches written since the
+/// last fsync are at risk. The operating system may write them back out of
+/// order, and some filesystems leave zero-filled ranges after a power loss,
+/// so unsynced data may be lost or corrupted, not only cut short. Recovery
+/// on open only repairs a torn record at the very end of the file; damage
+/// further in makes reads stop there with an error.
+/// Everything up to the last fsync is safe.
+///
+/// `flush_durable` on the writers and stores forces an fsync at any time,
+/// for example before shutting down or before telling a client its data is
+/// safe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DurabilityPolicy {
+    /// Fsync after every batch, before the write returns.
+    ///
+    /// Nothing acknowledged is ever lost. Every batch pays for a full fsync.
+    #[default]
+    Always,
+    /// Fsync after a batch once this much time has passed since the last
+    /// fsync.
+    ///
+    /// Loses up to the interval's worth of batches. The interval is only
+    /// looked at when writing, so the last batches before writes stop stay
+    /// at risk until the next write or `flush_durable`.
+    EveryInterval(Duration),
+    /// Fsync after every `n`-th batch.
+    ///
+    /// Loses up to `n - 1` batches, or more if writes stop before the next
+    /// fsync and `flush_durable` is not called. `0` behaves like `1`.
+    EveryNBatches(usize),
+    /// Never fsync; leave writing back to the operating system.
+    ///
+    /// Loses whatever the operating system had not written back yet, which
+    /// can be many seconds of batches. Only `flush_durable` forces them to
+    /// disk.
+    OsBuffered,
+}
+
+/// Tracks the batches written since the last fsync under a policy.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SyncSchedule {
+    policy: DurabilityPolicy,
+    /// Batches written since the last fsync
+    unsynced: usize,
+    last_sync: Instant,
+}
+
+impl SyncSchedule {
+    pub(crate) fn new(policy: DurabilityPolicy) -> Self {
+        Self {
+            policy,
+            unsynced: 0,
+            last_sync: Instant::now(),
+        }
+    }
+
+    /// Record `batches` more written batches, and say whether to fsync now.
+    pub(crate) fn written(&mut self, batches: usize) -> bool {
+        self.unsynced += batches;
+        match self.policy {
+            DurabilityPolicy::Always => true,
+            DurabilityPolicy::EveryInterval(interval) => self.last_sync.elapsed() >= interval,
+            DurabilityPolicy::EveryNBatches(n) => self.unsynced >= n.max(1),
+            DurabilityPolicy::OsBuffered => false,
+        }
+    }
+
+    /// Record an fsync covering everything written so far.
+    pub(crate) fn synced(&mut self) {
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_n_batches_syncs_on_the_nth() {
+        let mut schedule = SyncSchedule::new(DurabilityPolicy::EveryNBatches(3));
+        let mut syncs = Vec::new();
+        for _ in 0..7 {
+            let due = schedule.written(1);
+            if due {
+                schedule.synced();
+            }
+            syncs.push(due);
+        }
+        assert_eq!(syncs, [false, false, true, false, false, true, false]);
+
+        // A group of batches counts as that many
+        assert!(schedule.written(2));
+    }
+
+    #[test]
+    fn policies_without_counts() {
+        assert!(SyncSchedule::new(DurabilityPolicy::Always).written(1));
+        assert!(!SyncSchedule::new(DurabilityPolicy::OsBuffered).written(1_000));
+        assert!(SyncSchedule::new(DurabilityPolicy::EveryNBatches(0)).written(1));
+
+        let mut schedule = SyncSchedule::new(DurabilityPolicy::EveryInterval(Duration::ZERO));
+        assert!(schedule.written(1));
+        let mut schedule =
+            SyncSchedule::new(DurabilityPolicy::EveryInterval(Duration::from_secs(3600)));
+        assert!(!schedule.written(1_000));
+    }
+}
@@ -0,0 +1,323 @@
+// stainless_facts/src/io/index.rs
+//
+// Sparse timestamp index kept in a sidecar file next to a fact stream.
+
+use super::common::{self, LineScanner};
+use super::Codec;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Timestamp of an indexed fact, the byte offset of its line and the
+/// sequence number of the first fact on that line.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct IndexEntry(DateTime<Utc>, u64, u64);
+
+/// Maps timestamps and sequence numbers to byte offsets for every
+/// `interval`-th fact in a stream.
+///
+/// The sidecar holds one JSON entry per line and is only ever appended to,
+/// except when it is found to be stale and rebuilt from scratch. Sidecars
+/// written before entries carried sequence numbers no longer parse and are
+/// rebuilt too. It is an accelerator: losing it never loses facts.
+pub(crate) struct TimestampIndex {
+    sidecar: PathBuf,
+    interval: usize,
+    entries: Vec<IndexEntry>,
+    /// Byte offset in the stream up to which facts have been indexed
+    indexed_len: u64,
+    /// Number of facts before `indexed_len`
+    facts: u64,
+    /// Facts indexed since the last entry was recorded
+    pending: usize,
+}
+
+impl TimestampIndex {
+    /// Path of the sidecar file for a fact stream.
+    pub(crate) fn sidecar_path(stream: &Path) -> PathBuf {
+        let mut name = stream.as_os_str().to_owned();
+        name.push(".idx");
+        PathBuf::from(name)
+    }
+
+    /// Load the index for `stream`, rebuilding it if it is missing or stale.
+    pub(crate) fn open<C: Codec>(stream: &Path, interval: usize, codec: &C) -> io::Result<Self> {
+        let sidecar = Self::sidecar_path(stream);
+        let loaded = load_entries(&sidecar)?;
+
+        let mut index = Self {
+            sidecar,
+            interval: interval.max(1),
+            entries: Vec::new(),
+            indexed_len: 0,
+            facts: 0,
+            pending: 0,
+        };
+
+        match loaded {
+            Some(entries) if is_consistent(&entries, stream, codec)? => {
+                if let Some(&IndexEntry(_, offset, seq)) = entries.last() {
+                    index.indexed_len = offset;
+                    index.facts = seq;
+                }
+                index.entries = entries;
+            }
+            _ => {
+                // Missing, unreadable or stale: start over
+                File::create(&index.sidecar)?;
+            }
+        }
+
+        index.catch_up(stream, codec)?;
+        Ok(index)
+    }
+
+    /// Index any facts appended to `stream` since the last call.
+    ///
+    /// Only complete lines are indexed, so a batch that is still being
+    /// written is picked up by a later call.
+    pub(crate) fn catch_up<C: Codec>(&mut self, stream: &Path, codec: &C) -> io::Result<()> {
+        let mut file = match File::open(stream) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(self.indexed_len))?;
+
+        let mut scanner = LineScanner::new(BufReader::new(file), C::DELIMITER, self.indexed_len);
+        let mut added = Vec::new();
+
+        while let Some((offset, line)) = scanner.next_line()? {
+            let Some(count) = common::facts_on_line(codec, line).filter(|&count| count > 0) else {
+                continue;
+            };
+
+            // The entry we resumed from is scanned again; don't record it twice
+            let is_new = self.entries.last().map_or(true, |entry| entry.1 < offset);
+            if self.pending == 0 && is_new {
+                if let Some((timestamp, _)) = common::parse_timestamps(codec, line) {
+                    let entry = IndexEntry(timestamp, offset, self.facts);
+                    self.entries.push(entry);
+                    added.push(entry);
+                }
+            }
+            self.facts += count as u64;
+            self.pending = (self.pending + 1) % self.interval;
+        }
+
+        self.indexed_len = scanner.offset();
+        self.append_entries(&added)
+    }
+
+    /// Byte range that contains the first fact at or after `since`.
+    ///
+    /// Every fact before the start offset has a timestamp before `since`. The
+    /// end offset, if known, is the start of an indexed fact that is already
+    /// at or after `since`.
+    pub(crate) fn seek_range(&self, since: DateTime<Utc>) -> (u64, Option<u64>) {
+        let split = self.entries.partition_point(|entry| entry.0 < since);
+        let start = split.checked_sub(1).map_or(0, |i| self.entries[i].1);
+        let end = self.entries.get(split).map(|entry| entry.1);
+        (start, end)
+    }
+
+    /// Byte offset of the indexed line holding fact `seq`, or of the closest
+    /// indexed line before it, with the sequence number of its first fact.
+    pub(crate) fn seek_seq(&self, seq: u64) -> (u64, u64) {
+        let split = self.entries.partition_point(|entry| entry.2 <= seq);
+        split
+            .checked_sub(1)
+            .map_or((0, 0), |i| (self.entries[i].1, self.entries[i].2))
+    }
+
+    /// The closest line start at or before `offset` whose sequence number is
+    /// known, with that sequence number.
+    pub(crate) fn anchor(&self, offset: u64) -> (u64, u64) {
+        if offset >= self.indexed_len {
+            return (self.indexed_len, self.facts);
+        }
+        let split = self.entries.partition_point(|entry| entry.1 <= offset);
+        split
+            .checked_sub(1)
+            .map_or((0, 0), |i| (self.entries[i].1, self.entries[i].2))
+    }
+
+    fn append_entries(&self, entries: &[IndexEntry]) -> io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.sidecar)?;
+        let mut writer = BufWriter::new(file);
+        for entry in entries {
+            serde_json::to_writer(&mut writer, entry)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+}
+
+/// Read the sidecar, returning `None` if it is missing or cannot be parsed.
+fn load_entries(sidecar: &Path) -> io::Result<Option<Vec<IndexEntry>>> {
+    let file = match File::open(sidecar) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => return Ok(None),
+        }
+    }
+
+    Ok(Some(entries))
+}
+
+/// Check that the entries still describe the stream on disk.
+///
+/// Entries must be ordered, and the last one must point at a line that
+/// still carries the recorded timestamp. A stream that was truncated or
+/// rewritten fails this check.
+fn is_consistent<C: Codec>(entries: &[IndexEntry], stream: &Path, codec: &C) -> io::Result<bool> {
+    let ordered = entries
+        .windows(2)
+        .all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 < pair[1].1 && pair[0].2 < pair[1].2);
+    if !ordered {
+        return Ok(false);
+    }
+
+    let Some(&IndexEntry(timestamp, offset, _)) = entries.last() else {
+        return Ok(true);
+    };
+
+    let mut file = match File::open(stream) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if offset >= file.metadata()?.len() {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut scanner = LineScanner::new(BufReader::new(file), C::DELIMITER, offset);
+    let found = scanner
+        .next_line()?
+        .and_then(|(_, line)| common::parse_timestamps(codec, line))
+        .map(|(first, _)| first);
+    Ok(found == Some(timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{FactStreamWriter, JsonCodec};
+    use crate::testing::{at, item_facts};
+    use tempfile::TempDir;
+
+    fn write_facts(path: &Path, seconds: std::ops::Range<i64>) {
+        let mut writer = FactStreamWriter::open(path).unwrap();
+        writer.write_batch(&item_facts(seconds)).unwrap();
+    }
+
+    #[test]
+    fn records_every_interval_facts() {
+        let dir = TempDir::new().unwrap();
+        let stream = dir.path().join("facts.stream");
+        write_facts(&stream, 0..10);
+
+        let index = TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+
+        let timestamps: Vec<_> = index.entries.iter().map(|entry| entry.0).collect();
+        assert_eq!(timestamps, vec![at(0), at(4), at(8)]);
+    }
+
+    #[test]
+    fn seek_range_brackets_since() {
+        let dir = TempDir::new().unwrap();
+        let stream = dir.path().join("facts.stream");
+        write_facts(&stream, 0..10);
+
+        let index = TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+        let offsets: Vec<_> = index.entries.iter().map(|entry| entry.1).collect();
+
+        assert_eq!(index.seek_range(at(0)), (0, Some(offsets[0])));
+        assert_eq!(index.seek_range(at(4)), (offsets[0], Some(offsets[1])));
+        assert_eq!(index.seek_range(at(5)), (offsets[1], Some(offsets[2])));
+        assert_eq!(index.seek_range(at(9)), (offsets[2], None));
+    }
+
+    #[test]
+    fn catch_up_continues_interval_across_reopen() {
+        let dir = TempDir::new().unwrap();
+        let stream = dir.path().join("facts.stream");
+        write_facts(&stream, 0..6);
+        TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+
+        write_facts(&stream, 6..10);
+        let index = TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+
+        let timestamps: Vec<_> = index.entries.iter().map(|entry| entry.0).collect();
+        assert_eq!(timestamps, vec![at(0), at(4), at(8)]);
+    }
+
+    #[test]
+    fn entries_record_sequence_numbers() {
+        let dir = TempDir::new().unwrap();
+        let stream = dir.path().join("facts.stream");
+        write_facts(&stream, 0..3);
+        // A framed batch is one line holding several facts
+        let framed = item_facts(3..7);
+        FactStreamWriter::open(&stream)
+            .unwrap()
+            .with_batch_framing(true)
+            .write_batch(&framed)
+            .unwrap();
+        write_facts(&stream, 7..10);
+
+        let index = TimestampIndex::open(&stream, 2, &JsonCodec).unwrap();
+
+        let seqs: Vec<_> = index.entries.iter().map(|entry| entry.2).collect();
+        assert_eq!(seqs, vec![0, 2, 7, 9]);
+        assert_eq!(index.seek_seq(5).1, 2);
+        assert_eq!(index.anchor(u64::MAX), (index.indexed_len, 10));
+    }
+
+    #[test]
+    fn sidecar_without_sequence_numbers_is_rebuilt() {
+        let dir = TempDir::new().unwrap();
+        let stream = dir.path().join("facts.stream");
+        write_facts(&stream, 0..10);
+        let sidecar = TimestampIndex::sidecar_path(&stream);
+        std::fs::write(&sidecar, "[\"2024-01-15T10:00:00Z\",0]\n").unwrap();
+
+        let index = TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+
+        let seqs: Vec<_> = index.entries.iter().map(|entry| entry.2).collect();
+        assert_eq!(seqs, vec![0, 4, 8]);
+    }
+
+    #[test]
+    fn stale_sidecar_is_rebuilt() {
+        let dir = TempDir::new().unwrap();
+        let stream = dir.path().join("facts.stream");
+        write_facts(&stream, 0..10);
+        TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+
+        // Rewrite the stream so the recorded offsets no longer match
+        std::fs::remove_file(&stream).unwrap();
+        write_facts(&stream, 20..25);
+        let index = TimestampIndex::open(&stream, 4, &JsonCodec).unwrap();
+
+        let timestamps: Vec<_> = index.entries.iter().map(|entry| entry.0).collect();
+        assert_eq!(timestamps, vec![at(20), at(24)]);
+    }
+}
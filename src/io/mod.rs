@@ -2,15 +2,37 @@
 //
 // Sync I/O always available, async I/O with tokio feature
 
-mod common;
+mod codec;
+pub(crate) mod common;
+pub(crate) mod compression;
+mod durability;
+pub(crate) mod index;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+pub(crate) mod recovery;
+pub(crate) mod seek;
+pub(crate) mod segment;
+mod verify;
+
+pub use codec::{Codec, CodecError, JsonCodec};
+pub use durability::DurabilityPolicy;
+pub(crate) use durability::SyncSchedule;
+#[cfg(feature = "msgpack")]
+pub use msgpack::MessagePackCodec;
+pub(crate) use recovery::recover;
+pub use recovery::{RecoveryPolicy, RecoveryReport};
+pub use segment::SegmentPolicy;
+pub(crate) use verify::verify;
+pub use verify::{VerifyIssue, VerifyIssueKind, VerifyReport};
 
 // Sync I/O - always available
 mod sync;
+pub(crate) use sync::LOCK_RETRY_INTERVAL;
 pub use sync::{FactStreamReader, FactStreamWriter};
 
 // Async I/O - only with tokio feature
 #[cfg(feature = "tokio")]
-mod asyncio;
+pub(crate) mod asyncio;
 
 #[cfg(feature = "tokio")]
 pub use asyncio::{AsyncFactStreamReader, AsyncFactStreamWriter};
@@ -24,7 +46,7 @@ pub enum WriteError {
     Io(#[from] std::io::Error),
 
     #[error("Serialization failed: {0}")]
-    Serialization(#[from] serde_json::Error),
+    Serialization(#[from] CodecError),
 
     #[error("File is already locked by another process")]
     AlreadyLocked,
@@ -39,7 +61,10 @@ pub enum ReadError {
     Io(#[from] std::io::Error),
 
     #[error("Deserialization failed: {0}")]
-    Deserialization(#[from] serde_json::Error),
+    Deserialization(CodecError),
+
+    #[error("Checksum mismatch: line says {expected:08x}, contents hash to {actual:08x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
 
     #[error("File is already locked by another process")]
     AlreadyLocked,
@@ -47,3 +72,48 @@ pub enum ReadError {
     #[error("Failed to acquire lock within {0:?}")]
     LockTimeout(Duration),
 }
+
+impl WriteError {
+    /// The `serde_json` error behind a [`WriteError::Serialization`] from the
+    /// JSON codec, as that variant carried before codecs were pluggable.
+    pub fn as_json(&self) -> Option<&serde_json::Error> {
+        match self {
+            WriteError::Serialization(error) => error.as_json(),
+            _ => None,
+        }
+    }
+}
+
+impl ReadError {
+    /// The `serde_json` error behind a [`ReadError::Deserialization`] from
+    /// the JSON codec, as that variant carried before codecs were pluggable.
+    pub fn as_json(&self) -> Option<&serde_json::Error> {
+        match self {
+            ReadError::Deserialization(error) => error.as_json(),
+            _ => None,
+        }
+    }
+}
+
+impl From<CodecError> for ReadError {
+    fn from(error: CodecError) -> Self {
+        match error {
+            CodecError::ChecksumMismatch { expected, actual } => {
+                ReadError::ChecksumMismatch { expected, actual }
+            }
+            error => ReadError::Deserialization(error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ReadError {
+    fn from(error: serde_json::Error) -> Self {
+        ReadError::Deserialization(CodecError::Json(error))
+    }
+}
+
+impl From<serde_json::Error> for WriteError {
+    fn from(error: serde_json::Error) -> Self {
+        WriteError::Serialization(CodecError::Json(error))
+    }
+}
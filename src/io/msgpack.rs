@@ -0,0 +1,319 @@
+// stainless_facts/src/io/msgpack.rs
+//
+// Compact binary records: length-prefixed MessagePack, COBS-framed.
+
+use super::{Codec, CodecError};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Flags byte, then the payload length as a little-endian `u32`.
+const HEADER_LEN: usize = 5;
+/// Flag bit set when a CRC32C trails the payload.
+const HAS_CHECKSUM: u8 = 0x01;
+const CHECKSUM_LEN: usize = 4;
+
+/// MessagePack records, a compact binary alternative to JSON.
+///
+/// Facts use the [compact layout](Codec::COMPACT_FACTS): timestamps are
+/// stored as integer nanoseconds and the operation as a single byte, so
+/// timestamps before 1677 or after 2262 cannot be written.
+///
+/// Each record is a flags byte, the length of the MessagePack payload, the
+/// payload itself and an optional CRC32C of everything before it. The record is then COBS-encoded, which removes every zero byte from
+/// it, and ended with a zero byte. That keeps record boundaries findable
+/// from any offset, just like the newlines of a JSON stream.
+///
+/// Attribute values are written as maps with `t` and `v` keys, so they can
+/// still be read as [`UnknownAttribute`](crate::UnknownAttribute) by code
+/// that does not know their tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessagePackCodec;
+
+impl Codec for MessagePackCodec {
+    const DELIMITER: u8 = 0x00;
+    const COMPACT_FACTS: bool = true;
+
+    fn encode<T: Serialize>(
+        &self,
+        value: &T,
+        checksum: bool,
+        out: &mut Vec<u8>,
+    ) -> Result<(), CodecError> {
+        let flags = if checksum { HAS_CHECKSUM } else { 0 };
+        let mut record = vec![flags, 0, 0, 0, 0];
+        // Structs as maps, so a framed batch is told apart from a fact
+        value
+            .serialize(&mut rmp_serde::Serializer::new(&mut record).with_struct_map())
+            .map_err(|e| CodecError::Other(Box::new(e)))?;
+
+        let len = u32::try_from(record.len() - HEADER_LEN)
+            .map_err(|_| CodecError::Malformed("record exceeds 4 GiB".to_string()))?;
+        record[1..HEADER_LEN].copy_from_slice(&len.to_le_bytes());
+        if checksum {
+            let checksum = crc32c::crc32c(&record);
+            record.extend_from_slice(&checksum.to_le_bytes());
+        }
+
+        cobs_encode(&record, out);
+        out.push(Self::DELIMITER);
+        Ok(())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, record: &[u8]) -> Result<T, CodecError> {
+        let record = unframe(record)?;
+        let payload_end = HEADER_LEN + payload_len(&record);
+
+        if record[0] & HAS_CHECKSUM != 0 {
+            let mut expected = [0; CHECKSUM_LEN];
+            expected.copy_from_slice(&record[payload_end..]);
+            let expected = u32::from_le_bytes(expected);
+            let actual = crc32c::crc32c(&record[..payload_end]);
+            if actual != expected {
+                return Err(CodecError::ChecksumMismatch { expected, actual });
+            }
+        }
+
+        rmp_serde::from_slice(&record[HEADER_LEN..payload_end])
+            .map_err(|e| CodecError::Other(Box::new(e)))
+    }
+
+    fn has_checksum(&self, record: &[u8]) -> bool {
+        unframe(record).is_ok_and(|record| record[0] & HAS_CHECKSUM != 0)
+    }
+}
+
+/// Undo the COBS encoding of a record and check it against its header.
+fn unframe(record: &[u8]) -> Result<Vec<u8>, CodecError> {
+    let malformed = |reason: &str| CodecError::Malformed(reason.to_string());
+
+    let record = cobs_decode(record).ok_or_else(|| malformed("invalid COBS encoding"))?;
+    if record.len() < HEADER_LEN {
+        return Err(malformed("record is shorter than its header"));
+    }
+    if record[0] & !HAS_CHECKSUM != 0 {
+        return Err(malformed("unknown record flags"));
+    }
+
+    let checksum_len = if record[0] & HAS_CHECKSUM != 0 {
+        CHECKSUM_LEN
+    } else {
+        0
+    };
+    if record.len() != HEADER_LEN + payload_len(&record) + checksum_len {
+        return Err(malformed("record length does not match its header"));
+    }
+    Ok(record)
+}
+
+fn payload_len(record: &[u8]) -> usize {
+    let mut len = [0; 4];
+    len.copy_from_slice(&record[1..HEADER_LEN]);
+    u32::from_le_bytes(len) as usize
+}
+
+/// Append `data` to `out` with every zero byte encoded away.
+///
+/// Consistent Overhead Byte Stuffing: each block starts with a code byte
+/// giving the distance to the next zero, so the output grows by at most
+/// one byte in 254.
+fn cobs_encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut code_index = out.len();
+    let mut code = 1u8;
+    out.push(0);
+
+    for &byte in data {
+        if byte != 0 {
+            out.push(byte);
+            code += 1;
+        }
+        if byte == 0 || code == 0xff {
+            out[code_index] = code;
+            code_index = out.len();
+            code = 1;
+            out.push(0);
+        }
+    }
+    out[code_index] = code;
+}
+
+/// Reverse [`cobs_encode`], or `None` if `data` is not valid COBS.
+fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+
+    while i < data.len() {
+        let code = data[i] as usize;
+        if code == 0 || i + code > data.len() {
+            return None;
+        }
+        out.extend_from_slice(&data[i + 1..i + code]);
+        i += code;
+        // A full block is not followed by an implicit zero
+        if code < 0xff && i < data.len() {
+            out.push(0);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::common::{self, LineFormat};
+    use crate::testing;
+    use crate::{Fact, Operation, ReadError, UnknownAttribute};
+    use chrono::{DateTime, Utc};
+    use serde::{de::IgnoredAny, Deserialize};
+    use std::collections::VecDeque;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "t", content = "v")]
+    enum TestValue {
+        Count(u32),
+        Label(String),
+    }
+
+    /// A fact with a timestamp that needs every digit of a nanosecond.
+    fn fact(value: TestValue, operation: Operation) -> Fact<String, TestValue, String> {
+        let timestamp: DateTime<Utc> = "2024-01-15T10:00:00.123456789Z".parse().unwrap();
+        testing::fact("item1", value, timestamp, operation)
+    }
+
+    fn encode(fact: &Fact<String, TestValue, String>, checksum: bool) -> Vec<u8> {
+        let format = LineFormat {
+            framed: false,
+            checksums: checksum,
+        };
+        let mut record =
+            common::serialize_batch(&MessagePackCodec, std::slice::from_ref(fact), format).unwrap();
+        assert_eq!(record.pop(), Some(MessagePackCodec::DELIMITER));
+        record
+    }
+
+    fn decode<V: serde::de::DeserializeOwned>(
+        record: &[u8],
+    ) -> Result<Fact<String, V, String>, ReadError> {
+        let mut decoded = VecDeque::new();
+        common::decode_line(&MessagePackCodec, record, &mut decoded)?;
+        Ok(decoded.pop_front().unwrap())
+    }
+
+    #[test]
+    fn cobs_round_trips_zeros_and_long_runs() {
+        let cases: [Vec<u8>; 6] = [
+            vec![],
+            vec![0],
+            vec![0, 0, 1, 0],
+            vec![7; 254],
+            [vec![7; 254], vec![0, 7]].concat(),
+            (0..=255).cycle().take(1000).collect(),
+        ];
+
+        for data in cases {
+            let mut encoded = Vec::new();
+            cobs_encode(&data, &mut encoded);
+            assert!(!encoded.contains(&0));
+            assert_eq!(cobs_decode(&encoded), Some(data));
+        }
+    }
+
+    #[test]
+    fn facts_round_trip_with_and_without_checksum() {
+        let fact = fact(TestValue::Label("a\nb\0c".to_string()), Operation::Retract);
+
+        for checksum in [false, true] {
+            let record = encode(&fact, checksum);
+            assert!(!record.contains(&0));
+            assert_eq!(MessagePackCodec.has_checksum(&record), checksum);
+            assert_eq!(decode::<TestValue>(&record).unwrap(), fact);
+        }
+    }
+
+    #[test]
+    fn facts_serialize_as_usual_outside_the_codec() {
+        let fact = fact(TestValue::Count(7), Operation::Retract);
+        let bytes = rmp_serde::to_vec(&fact).unwrap();
+
+        let (_, _, timestamp, _, operation): (String, IgnoredAny, String, String, String) =
+            rmp_serde::from_slice(&bytes).unwrap();
+
+        assert_eq!(timestamp, "2024-01-15T10:00:00.123456789Z");
+        assert_eq!(operation, "Retract");
+        assert_eq!(
+            rmp_serde::from_slice::<Fact<_, _, _>>(&bytes).unwrap(),
+            fact
+        );
+    }
+
+    #[test]
+    fn timestamps_are_nanos_and_operations_are_bytes() {
+        let fact = fact(TestValue::Count(7), Operation::Retract);
+        let record = unframe(&encode(&fact, false)).unwrap();
+
+        let (_, _, nanos, _, operation): (String, IgnoredAny, i64, String, u8) =
+            rmp_serde::from_slice(&record[HEADER_LEN..]).unwrap();
+
+        assert_eq!(nanos, fact.timestamp().timestamp_nanos_opt().unwrap());
+        assert_eq!(operation, 1);
+    }
+
+    #[test]
+    fn smaller_than_json() {
+        let fact = fact(TestValue::Count(7), Operation::Assert);
+        let json = serde_json::to_vec(&fact).unwrap();
+
+        assert!(encode(&fact, false).len() < json.len() * 2 / 3);
+    }
+
+    #[test]
+    fn unknown_tags_read_as_unknown_attribute() {
+        let record = encode(&fact(TestValue::Count(7), Operation::Assert), false);
+
+        let unknown = decode::<UnknownAttribute>(&record).unwrap();
+
+        assert_eq!(unknown.value().t, "Count");
+        assert_eq!(unknown.value().v, serde_json::json!(7));
+    }
+
+    #[test]
+    fn framed_batches_and_timestamps_decode() {
+        let facts = [
+            fact(TestValue::Count(1), Operation::Assert),
+            fact(TestValue::Count(2), Operation::Retract),
+        ];
+        let format = LineFormat {
+            framed: true,
+            checksums: true,
+        };
+        let buffer = common::serialize_batch(&MessagePackCodec, &facts, format).unwrap();
+
+        let mut decoded = VecDeque::new();
+        common::decode_line(&MessagePackCodec, &buffer, &mut decoded).unwrap();
+        assert_eq!(decoded, facts);
+        assert_eq!(
+            common::parse_timestamps(&MessagePackCodec, &buffer),
+            Some((*facts[0].timestamp(), *facts[1].timestamp()))
+        );
+    }
+
+    #[test]
+    fn damaged_records_are_rejected() {
+        let fact = fact(TestValue::Count(7), Operation::Assert);
+
+        // Turn the Assert into a Retract behind the checksum's back
+        let mut record = unframe(&encode(&fact, true)).unwrap();
+        let operation = record.len() - CHECKSUM_LEN - 1;
+        record[operation] = 1;
+        let mut flipped = Vec::new();
+        cobs_encode(&record, &mut flipped);
+        let result = decode::<TestValue>(&flipped);
+        assert!(matches!(result, Err(ReadError::ChecksumMismatch { .. })));
+
+        let record = encode(&fact, false);
+        let torn = &record[..record.len() - 3];
+        let result = decode::<TestValue>(torn);
+        assert!(matches!(
+            result,
+            Err(ReadError::Deserialization(CodecError::Malformed(_)))
+        ));
+    }
+}
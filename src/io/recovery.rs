@@ -0,0 +1,241 @@
+// stainless_facts/src/io/recovery.rs
+//
+// Repair of fact streams left behind by interrupted writes.
+
+use super::{common, sync::lock_exclusive, Codec, WriteError};
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What to do with an incomplete record at the end of a fact stream.
+///
+/// A process that dies partway through a write can leave a partial line at
+/// the end of the stream. Left in place, the next append would be glued onto
+/// it and break every later read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Cut the incomplete record off the end of the stream.
+    Truncate,
+    /// Move the incomplete record to a `<path>.corrupt` sidecar, then cut it off.
+    #[default]
+    Quarantine,
+}
+
+/// What recovery did to a fact stream when it was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Length of the stream after recovery
+    pub valid_len: u64,
+    /// Bytes of incomplete records removed from the end of the stream
+    pub removed_bytes: u64,
+    /// Sidecar the removed bytes were appended to, under [`RecoveryPolicy::Quarantine`]
+    pub quarantine_path: Option<PathBuf>,
+    /// Whether a complete final record lacked its newline, which was appended
+    pub appended_newline: bool,
+}
+
+impl RecoveryReport {
+    /// Whether the stream was left untouched.
+    pub fn is_clean(&self) -> bool {
+        self.removed_bytes == 0 && !self.appended_newline
+    }
+}
+
+/// Path of the sidecar that quarantined records are moved to.
+pub(crate) fn quarantine_path(stream: &Path) -> PathBuf {
+    let mut name = stream.as_os_str().to_owned();
+    name.push(".corrupt");
+    PathBuf::from(name)
+}
+
+/// Detect and repair an incomplete record at the end of the stream.
+///
+/// Repairs happen under the exclusive write lock, so a batch that another
+/// writer is still writing is never mistaken for a torn one. The lock is
+/// waited for like an append does, up to `lock_timeout`, checking every
+/// `retry_interval`.
+pub(crate) fn recover<C: Codec>(
+    codec: &C,
+    path: &Path,
+    policy: RecoveryPolicy,
+    lock_timeout: Duration,
+    retry_interval: Duration,
+) -> Result<RecoveryReport, WriteError> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(RecoveryReport::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Cheap check without the lock: almost every stream ends with a newline
+    let len = file.metadata()?.len();
+    if common::complete_len(&mut file, C::DELIMITER)? == len {
+        return Ok(RecoveryReport {
+            valid_len: len,
+            ..RecoveryReport::default()
+        });
+    }
+
+    lock_exclusive(&file, lock_timeout, retry_interval)?;
+
+    let result = repair(codec, &mut file, path, policy);
+
+    // Always release lock, even on error
+    let _ = FileExt::unlock(&file);
+
+    result
+}
+
+fn repair<C: Codec>(
+    codec: &C,
+    file: &mut File,
+    path: &Path,
+    policy: RecoveryPolicy,
+) -> Result<RecoveryReport, WriteError> {
+    let len = file.metadata()?.len();
+    let valid_len = common::complete_len(file, C::DELIMITER)?;
+    let mut report = RecoveryReport {
+        valid_len: len,
+        ..RecoveryReport::default()
+    };
+
+    // The write we saw in progress may have finished while we waited
+    if valid_len == len {
+        return Ok(report);
+    }
+
+    let mut tail = vec![0; (len - valid_len) as usize];
+    file.seek(SeekFrom::Start(valid_len))?;
+    file.read_exact(&mut tail)?;
+
+    if common::parse_timestamps(codec, &tail).is_some() {
+        // The record itself is intact, only its delimiter is missing
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&[C::DELIMITER])?;
+        file.sync_all()?;
+        report.valid_len = len + 1;
+        report.appended_newline = true;
+        return Ok(report);
+    }
+
+    if policy == RecoveryPolicy::Quarantine {
+        let quarantine = quarantine_path(path);
+        let mut sidecar = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&quarantine)?;
+        sidecar.write_all(&tail)?;
+        sidecar.write_all(&[C::DELIMITER])?;
+        sidecar.sync_all()?;
+        report.quarantine_path = Some(quarantine);
+    }
+
+    file.set_len(valid_len)?;
+    file.sync_all()?;
+    report.valid_len = valid_len;
+    report.removed_bytes = len - valid_len;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::JsonCodec;
+    use tempfile::TempDir;
+
+    const FACT: &[u8] =
+        b"[\"item1\",{\"t\":\"Count\",\"v\":1},\"2024-01-15T10:00:00Z\",\"source1\",\"Assert\"]";
+
+    fn recover_now(path: &Path, policy: RecoveryPolicy) -> Result<RecoveryReport, WriteError> {
+        recover(&JsonCodec, path, policy, Duration::ZERO, Duration::ZERO)
+    }
+
+    fn stream_with(dir: &TempDir, contents: &[u8]) -> PathBuf {
+        let path = dir.path().join("facts.stream");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn clean_stream_is_untouched() {
+        let dir = TempDir::new().unwrap();
+        let path = stream_with(&dir, b"[1]\n[2]\n");
+
+        let report = recover_now(&path, RecoveryPolicy::Truncate).unwrap();
+
+        assert!(report.is_clean());
+        assert_eq!(report.valid_len, 8);
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1]\n[2]\n");
+    }
+
+    #[test]
+    fn missing_stream_is_clean() {
+        let dir = TempDir::new().unwrap();
+
+        let report = recover_now(&dir.path().join("missing"), RecoveryPolicy::Truncate).unwrap();
+
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn truncate_removes_torn_record() {
+        let dir = TempDir::new().unwrap();
+        let path = stream_with(&dir, b"[1]\n[2]\n[3, \"tor");
+
+        let report = recover_now(&path, RecoveryPolicy::Truncate).unwrap();
+
+        assert_eq!(report.removed_bytes, 8);
+        assert_eq!(report.quarantine_path, None);
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1]\n[2]\n");
+    }
+
+    #[test]
+    fn quarantine_moves_torn_record_to_sidecar() {
+        let dir = TempDir::new().unwrap();
+        let path = stream_with(&dir, b"[1]\n[2]\n[3, \"tor");
+
+        let report = recover_now(&path, RecoveryPolicy::Quarantine).unwrap();
+
+        let sidecar = quarantine_path(&path);
+        assert_eq!(report.quarantine_path, Some(sidecar.clone()));
+        assert_eq!(std::fs::read(&sidecar).unwrap(), b"[3, \"tor\n");
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1]\n[2]\n");
+    }
+
+    #[test]
+    fn complete_record_without_newline_is_terminated() {
+        let dir = TempDir::new().unwrap();
+        let path = stream_with(&dir, FACT);
+
+        let report = recover_now(&path, RecoveryPolicy::Truncate).unwrap();
+
+        assert!(report.appended_newline);
+        assert_eq!(report.removed_bytes, 0);
+        assert_eq!(std::fs::read(&path).unwrap(), [FACT, b"\n"].concat());
+    }
+
+    #[test]
+    fn waits_for_lock_up_to_timeout() {
+        let dir = TempDir::new().unwrap();
+        let path = stream_with(&dir, b"[1]\n[2]\n[3, \"tor");
+        let writer = File::open(&path).unwrap();
+        FileExt::lock_exclusive(&writer).unwrap();
+
+        let result = recover_now(&path, RecoveryPolicy::Truncate);
+        assert!(matches!(result, Err(WriteError::AlreadyLocked)));
+
+        let timeout = Duration::from_millis(50);
+        let result = recover(
+            &JsonCodec,
+            &path,
+            RecoveryPolicy::Truncate,
+            timeout,
+            Duration::from_millis(10),
+        );
+        assert!(matches!(result, Err(WriteError::LockTimeout(t)) if t == timeout));
+        assert_eq!(std::fs::read(&path).unwrap(), b"[1]\n[2]\n[3, \"tor");
+    }
+}
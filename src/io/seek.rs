@@ -0,0 +1,204 @@
+// stainless_facts/src/io/seek.rs
+//
+// Binary search over a timestamp-ordered fact stream.
+
+use super::{common, compression, Codec};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+/// Below this many bytes a linear scan is cheaper than another probe.
+const LINEAR_WINDOW: u64 = 4096;
+
+/// Find a byte offset from which a linear scan for `since` can start.
+///
+/// `low` must be the start of a line before which every fact is older than
+/// `since`, and the search never looks past `high` (or the end of the file).
+/// The stream is bisected on byte offsets: each probe resyncs to the next
+/// newline and decodes only the timestamp column of that line.
+///
+/// The search relies on timestamps being non-decreasing, which `FactStore`
+/// enforces on append. If the probes reveal an ordering violation, as can
+/// happen with legacy data, it gives up and returns `low`.
+pub(crate) fn seek_offset<C: Codec>(
+    codec: &C,
+    path: &Path,
+    since: DateTime<Utc>,
+    low: u64,
+    high: Option<u64>,
+) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    // A compressed segment cannot be seeked, so it is read from the start
+    if compression::is_compressed(&mut file)? {
+        return Ok(0);
+    }
+    let len = file.metadata()?.len();
+    let high = high.map_or(len, |high| high.min(len));
+
+    let mut bisect = Bisect {
+        codec,
+        reader: BufReader::new(file),
+        line: Vec::with_capacity(1024),
+        probes: Vec::new(),
+    };
+    bisect.run(since, low, high)
+}
+
+struct Bisect<'a, C> {
+    codec: &'a C,
+    reader: BufReader<File>,
+    line: Vec<u8>,
+    /// Offset and timestamp of every probed line
+    probes: Vec<(u64, DateTime<Utc>)>,
+}
+
+impl<C: Codec> Bisect<'_, C> {
+    fn run(&mut self, since: DateTime<Utc>, start: u64, end: u64) -> io::Result<u64> {
+        // The first fact is both a shortcut and the reference for ordering checks
+        let mut low = match self.probe(start, end)? {
+            Some((line_end, first)) if first < since => line_end,
+            _ => return Ok(start),
+        };
+        let mut high = end;
+
+        while high.saturating_sub(low) > LINEAR_WINDOW {
+            let mid = low + (high - low) / 2;
+            let line_start = self.resync(mid)?;
+
+            match self.probe(line_start, high)? {
+                Some((line_end, timestamp)) => {
+                    if !self.is_ordered() {
+                        return Ok(start);
+                    }
+                    if timestamp < since {
+                        low = line_end;
+                    } else {
+                        high = mid;
+                    }
+                }
+                None => high = mid,
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// Position the reader at the first line starting at or after `offset`.
+    fn resync(&mut self, offset: u64) -> io::Result<u64> {
+        // Starting one byte early keeps a line that begins exactly at `offset`
+        self.reader.seek(SeekFrom::Start(offset - 1))?;
+        self.line.clear();
+        let skipped = self.reader.read_until(C::DELIMITER, &mut self.line)?;
+        Ok(offset - 1 + skipped as u64)
+    }
+
+    /// Decode the timestamp of the line at `offset`, returning where it ends.
+    ///
+    /// Returns `None` for lines at or past `high`, torn lines and lines that
+    /// are not facts.
+    fn probe(&mut self, offset: u64, high: u64) -> io::Result<Option<(u64, DateTime<Utc>)>> {
+        if offset >= high {
+            return Ok(None);
+        }
+
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.line.clear();
+        let read = self.reader.read_until(C::DELIMITER, &mut self.line)?;
+        if self.line.last() != Some(&C::DELIMITER) {
+            return Ok(None);
+        }
+
+        // A framed batch may only be skipped once all of its facts are too old
+        Ok(
+            common::parse_timestamps(self.codec, &self.line).map(|(_, timestamp)| {
+                self.probes.push((offset, timestamp));
+                (offset + read as u64, timestamp)
+            }),
+        )
+    }
+
+    /// Check the latest probe against all earlier ones.
+    fn is_ordered(&self) -> bool {
+        let Some((&(offset, timestamp), earlier)) = self.probes.split_last() else {
+            return true;
+        };
+
+        earlier.iter().all(|&(other_offset, other_timestamp)| {
+            (other_offset < offset && other_timestamp <= timestamp)
+                || (other_offset > offset && other_timestamp >= timestamp)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{FactStreamWriter, JsonCodec};
+    use crate::testing::{at, item_facts};
+    use tempfile::TempDir;
+
+    /// Write one fact per timestamp and return the offset of each line.
+    fn write_facts(path: &Path, seconds: impl IntoIterator<Item = i64>) -> Vec<u64> {
+        let mut writer = FactStreamWriter::open(path).unwrap();
+        writer.write_batch(&item_facts(seconds)).unwrap();
+
+        let contents = std::fs::read(path).unwrap();
+        let mut offsets = vec![0];
+        offsets.extend(
+            contents
+                .iter()
+                .enumerate()
+                .filter(|(_, byte)| **byte == b'\n')
+                .map(|(i, _)| i as u64 + 1),
+        );
+        offsets.pop();
+        offsets
+    }
+
+    #[test]
+    fn lands_close_before_first_matching_line() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let offsets = write_facts(&path, 0..5000);
+
+        let offset = seek_offset(&JsonCodec, &path, at(3217), 0, None).unwrap();
+
+        assert!(offsets.contains(&offset));
+        assert!(offset <= offsets[3217]);
+        assert!(offsets[3217] - offset <= LINEAR_WINDOW);
+    }
+
+    #[test]
+    fn stays_at_start_when_first_line_matches() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        write_facts(&path, 100..5000);
+
+        assert_eq!(seek_offset(&JsonCodec, &path, at(50), 0, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn seeks_to_end_when_since_is_after_all_facts() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let offsets = write_facts(&path, 0..5000);
+
+        let offset = seek_offset(&JsonCodec, &path, at(10_000), 0, None).unwrap();
+
+        assert!(offset >= offsets[4999] - LINEAR_WINDOW);
+    }
+
+    #[test]
+    fn falls_back_to_start_on_ordering_violation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        // Legacy data: the first fact is newer than most of the ones after it
+        write_facts(&path, std::iter::once(3000).chain(0..5000));
+
+        assert_eq!(
+            seek_offset(&JsonCodec, &path, at(4000), 0, None).unwrap(),
+            0
+        );
+    }
+}
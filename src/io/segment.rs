@@ -0,0 +1,855 @@
+// stainless_facts/src/io/segment.rs
+//
+// Segmented storage: a directory of rolling segment files and a manifest.
+
+use super::common::{self, LineScanner};
+use super::compression::{self, StreamFile};
+use super::sync::{lock_exclusive, LOCK_RETRY_INTERVAL};
+use super::{Codec, WriteError};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+/// Locked by writers while they change the manifest or the active segment
+const MANIFEST_LOCK: &str = "manifest.lock";
+const SEGMENT_EXTENSION: &str = "facts";
+
+/// When a segmented store closes its active segment and starts a new one.
+///
+/// A segment is only ever rolled between batches, so it may end up somewhat
+/// larger or longer than the limits by the size of one batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentPolicy {
+    /// Roll once the active segment has grown to this many bytes
+    pub max_bytes: u64,
+    /// Roll once a batch reaches this far past the segment's first fact
+    pub max_span: Option<Duration>,
+    /// zstd level to compress segments with once they are sealed, set
+    /// through `with_compression`
+    compression: Option<i32>,
+}
+
+impl SegmentPolicy {
+    /// Roll segments once they reach `max_bytes`.
+    pub fn by_size(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            max_span: None,
+            compression: None,
+        }
+    }
+
+    /// Also roll segments once they span more than `max_span` of fact time.
+    pub fn with_max_span(mut self, max_span: Duration) -> Self {
+        self.max_span = Some(max_span);
+        self
+    }
+
+    /// Compress each segment with zstd at `level` once it is sealed.
+    ///
+    /// The sealed segment is replaced by a `.zst` file that readers
+    /// decompress transparently. The active segment is never compressed.
+    /// Compression happens after the append that sealed the segment has
+    /// released the store's locks, so other appends and readers do not wait
+    /// for it, and the plain segment is read until its copy replaces it.
+    ///
+    /// Sealed segments found uncompressed on open, because compression was
+    /// enabled later or was interrupted, are compressed after the next
+    /// append too. A segment that fails to compress is retried after the
+    /// append after that; see
+    /// [`FactStore::compress_sealed`](crate::FactStore::compress_sealed) to
+    /// retry at once and learn why it failed.
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression = Some(level);
+        self
+    }
+}
+
+impl Default for SegmentPolicy {
+    /// 64 MiB segments without a time bound.
+    fn default() -> Self {
+        Self::by_size(64 * 1024 * 1024)
+    }
+}
+
+/// Where a segment is and which facts it holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SegmentMeta {
+    /// File name relative to the store directory
+    file: String,
+    /// Timestamp of the first fact, once one has been written
+    first: Option<DateTime<Utc>>,
+    /// Timestamp of the last fact; `None` while the segment is active
+    last: Option<DateTime<Utc>>,
+    /// Length of the file in bytes; `None` while the segment is active
+    len: Option<u64>,
+    /// Number of facts; `None` while the segment is active, and in
+    /// manifests written before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    facts: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    segments: Vec<SegmentMeta>,
+}
+
+/// The segments of a segmented store, oldest first.
+///
+/// Only the last segment is ever written to. The manifest is rewritten
+/// atomically when a segment is rolled, so it always lists every segment
+/// that holds facts. The active segment's last timestamp is not recorded
+/// there; it is read from the end of the file like in a single-file store.
+///
+/// Several processes may append to the same directory. Each one changes
+/// the manifest or writes to the active segment only under an exclusive
+/// lock on `manifest.lock`, and re-reads the manifest once it holds it.
+pub(crate) struct SegmentLog<C> {
+    dir: PathBuf,
+    policy: SegmentPolicy,
+    codec: C,
+    segments: Vec<SegmentMeta>,
+    /// Sealed segments still to be compressed, oldest first
+    pending: Vec<PathBuf>,
+    /// How long to wait for another process's lock on the manifest
+    lock_timeout: std::time::Duration,
+    retry_interval: std::time::Duration,
+}
+
+/// Exclusive lock on the manifest of a segmented store, released on drop.
+///
+/// Held from choosing the segment to append to until the batch has been
+/// written, so that no other process can seal the segment in between.
+pub(crate) struct ManifestLock(File);
+
+impl Drop for ManifestLock {
+    fn drop(&mut self) {
+        let _ = fs2::FileExt::unlock(&self.0);
+    }
+}
+
+impl<C: Codec> SegmentLog<C> {
+    /// Open the segments in `dir`, creating the directory if needed.
+    ///
+    /// A missing manifest is rebuilt from the segment files in the directory.
+    pub(crate) fn open(dir: &Path, policy: SegmentPolicy, codec: C) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let manifest = match fs::read(dir.join(MANIFEST)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => rebuild_manifest(&codec, dir)?,
+            Err(e) => return Err(e),
+        };
+
+        let mut log = Self {
+            dir: dir.to_path_buf(),
+            policy,
+            codec,
+            segments: manifest.segments,
+            pending: Vec::new(),
+            lock_timeout: std::time::Duration::ZERO,
+            retry_interval: LOCK_RETRY_INTERVAL,
+        };
+        if log.segments.is_empty() {
+            // Saved by the first append, under the lock
+            log.segments
+                .push(SegmentMeta::active(segment_name(0), None));
+        }
+        if policy.compression.is_some() {
+            // Sealed before compression was enabled, or by a process that
+            // stopped before compressing them
+            let sealed = &log.segments[..log.segments.len() - 1];
+            log.pending = sealed
+                .iter()
+                .filter(|segment| !is_compressed_name(&segment.file))
+                .map(|segment| log.dir.join(&segment.file))
+                .collect();
+        }
+        Ok(log)
+    }
+
+    /// Wait up to `timeout` for another process's lock on the manifest,
+    /// checking every `retry_interval`. By default, fail at once.
+    pub(crate) fn with_lock_wait(
+        mut self,
+        timeout: std::time::Duration,
+        retry_interval: std::time::Duration,
+    ) -> Self {
+        self.lock_timeout = timeout;
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Path of the segment that appends go to.
+    pub(crate) fn active_path(&self) -> PathBuf {
+        self.dir.join(&self.active().file)
+    }
+
+    /// Timestamp of the last fact in the sealed segments.
+    pub(crate) fn sealed_latest(&self) -> Option<DateTime<Utc>> {
+        self.segments.iter().rev().find_map(|segment| segment.last)
+    }
+
+    /// Prepare to append a batch spanning `first..=last`, rolling if due.
+    ///
+    /// Returns the path of the segment the batch must be written to, and the
+    /// lock on the manifest to hold until it has been written. Segments
+    /// rolled to by other processes are taken into account.
+    pub(crate) fn prepare_append(
+        &mut self,
+        first: DateTime<Utc>,
+        last: DateTime<Utc>,
+    ) -> Result<(PathBuf, ManifestLock), WriteError> {
+        let lock = self.lock()?;
+        let active_path = self.active_path();
+        let len = match fs::metadata(&active_path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        let Some(active_first) = self.active().first else {
+            // The first batch of a fresh segment: remember where it starts
+            self.active_mut().first = Some(first);
+            self.save()?;
+            return Ok((active_path, lock));
+        };
+
+        let too_big = len >= self.policy.max_bytes;
+        let too_long = self
+            .policy
+            .max_span
+            .is_some_and(|span| last - active_first > span);
+        if len > 0 && (too_big || too_long) {
+            self.roll(&active_path, len, first)?;
+        }
+
+        Ok((self.active_path(), lock))
+    }
+
+    /// Segment files that may hold facts at or after `since`, oldest first.
+    ///
+    /// Sealed segments whose last fact is older than `since` are skipped.
+    pub(crate) fn files_from(&self, since: DateTime<Utc>) -> Vec<PathBuf> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.last.map_or(true, |last| last >= since))
+            .map(|segment| self.dir.join(&segment.file))
+            .collect()
+    }
+
+    /// Segment files from the one holding fact `seq` onwards, oldest first,
+    /// with the sequence number of the first fact in the first of them.
+    ///
+    /// Facts past the sealed segments are looked for in the active one.
+    pub(crate) fn files_from_seq(&mut self, seq: u64) -> io::Result<(Vec<PathBuf>, u64)> {
+        let sealed = self.segments.len() - 1;
+        let mut start = sealed;
+        let mut base = 0;
+        for i in 0..sealed {
+            let facts = self.sealed_facts(i)?;
+            if base + facts > seq {
+                start = i;
+                break;
+            }
+            base += facts;
+        }
+
+        let files = self.segments[start..]
+            .iter()
+            .map(|segment| self.dir.join(&segment.file))
+            .collect();
+        Ok((files, base))
+    }
+
+    /// Sequence number of the first fact in the segment file at `path`.
+    ///
+    /// This is the number of facts in all segments numbered below it.
+    pub(crate) fn first_seq(&mut self, path: &Path) -> io::Result<u64> {
+        let Some(number) = sequence(path) else {
+            return Ok(0);
+        };
+        let mut seq = 0;
+        for i in 0..self.segments.len() {
+            if sequence(Path::new(&self.segments[i].file)).map_or(true, |other| other >= number) {
+                continue;
+            }
+            seq += if i + 1 < self.segments.len() {
+                self.sealed_facts(i)?
+            } else {
+                // Only when `path` was rolled to by someone else
+                let active = self.active_path();
+                common::count_facts(&self.codec, &active, 0, u64::MAX)?
+            };
+        }
+        Ok(seq)
+    }
+
+    /// Number of facts in the sealed segment at position `i`.
+    ///
+    /// Counts missing from an older manifest are counted from the file once.
+    fn sealed_facts(&mut self, i: usize) -> io::Result<u64> {
+        if let Some(facts) = self.segments[i].facts {
+            return Ok(facts);
+        }
+        let path = self.dir.join(&self.segments[i].file);
+        let facts = common::count_facts(&self.codec, &path, 0, u64::MAX)?;
+        self.segments[i].facts = Some(facts);
+        Ok(facts)
+    }
+
+    /// Seal the active segment and start a new one whose first fact is `first`.
+    ///
+    /// The sealed segment is left uncompressed; it is queued for
+    /// [`compress_sealed`] instead, so that rolling stays cheap.
+    fn roll(&mut self, active_path: &Path, len: u64, first: DateTime<Utc>) -> io::Result<()> {
+        // Batches may have been left unsynced under a relaxed durability
+        // policy, and a sealed segment is never written to again
+        OpenOptions::new()
+            .append(true)
+            .open(active_path)?
+            .sync_all()?;
+        let bounds = timestamp_bounds(&self.codec, active_path)?;
+        let facts = common::count_facts(&self.codec, active_path, 0, len)?;
+        let active = self.active_mut();
+        active.last = bounds.map(|(_, last)| last).or(active.first);
+        active.len = Some(len);
+        active.facts = Some(facts);
+        if self.policy.compression.is_some() {
+            self.pending.push(active_path.to_path_buf());
+        }
+
+        let name = segment_name(self.segments.len());
+        self.segments.push(SegmentMeta::active(name, Some(first)));
+        self.save()
+    }
+
+    /// Take the sealed segments waiting to be compressed, with the level to
+    /// compress them at.
+    ///
+    /// Each segment is handed out once, until it is put back with
+    /// [`SegmentLog::requeue`]; see [`compress_sealed`].
+    #[cfg(feature = "compression")]
+    fn take_pending(&mut self) -> Option<(Vec<PathBuf>, i32)> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let level = self.policy.compression?;
+        Some((std::mem::take(&mut self.pending), level))
+    }
+
+    /// Queue sealed segments taken with [`SegmentLog::take_pending`] again,
+    /// ahead of any sealed since.
+    #[cfg(feature = "compression")]
+    fn requeue(&mut self, plain: impl IntoIterator<Item = PathBuf>) {
+        let sealed_since = std::mem::take(&mut self.pending);
+        self.pending.extend(plain);
+        self.pending.extend(sealed_since);
+    }
+
+    /// Point the manifest at the compressed copy of the sealed segment at
+    /// `plain`, then remove the plain file.
+    #[cfg(feature = "compression")]
+    fn replace_with_compressed(
+        &mut self,
+        plain: &Path,
+        compressed: &Path,
+    ) -> Result<(), WriteError> {
+        let _lock = self.lock()?;
+        let name = file_name(plain);
+        let compressed_name = file_name(compressed);
+        let len = fs::metadata(compressed)?.len();
+        let Some(segment) = self
+            .segments
+            .iter_mut()
+            .find(|segment| segment.file == name)
+        else {
+            // Swapped already, by another process or by an attempt that
+            // failed to remove the plain file
+            if self.segments.iter().any(|s| s.file == compressed_name) {
+                remove_if_exists(plain)?;
+            }
+            return Ok(());
+        };
+        segment.file = compressed_name;
+        segment.len = Some(len);
+        self.save()?;
+
+        // Only once the manifest points at the compressed copy. Readers
+        // that listed the plain file fall back to the compressed one
+        Ok(remove_if_exists(plain)?)
+    }
+
+    /// Whether the manifest, as last read, lists the sealed segment at
+    /// `plain` uncompressed.
+    #[cfg(feature = "compression")]
+    fn lists(&self, plain: &Path) -> bool {
+        let name = file_name(plain);
+        self.segments.iter().any(|segment| segment.file == name)
+    }
+
+    /// Take the lock on the manifest, then pick up any changes other
+    /// processes made to it.
+    fn lock(&mut self) -> Result<ManifestLock, WriteError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.dir.join(MANIFEST_LOCK))?;
+        lock_exclusive(&file, self.lock_timeout, self.retry_interval)?;
+        self.reload()?;
+        Ok(ManifestLock(file))
+    }
+
+    /// Read the manifest again, in case another process rewrote it.
+    ///
+    /// The manifest is small and replaced atomically, so it is read whole.
+    /// Fact counts this process made for segments the manifest has none
+    /// for are kept, and segments another process compressed are no longer
+    /// queued for compression here.
+    pub(crate) fn reload(&mut self) -> io::Result<()> {
+        let mut manifest: Manifest = match fs::read(self.dir.join(MANIFEST)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            // Not saved yet; this process's view is all there is
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if manifest.segments.is_empty() {
+            return Ok(());
+        }
+
+        for segment in manifest.segments.iter_mut().filter(|s| s.facts.is_none()) {
+            let counted = self
+                .segments
+                .iter()
+                .find(|s| s.file == segment.file && s.len == segment.len);
+            segment.facts = counted.and_then(|s| s.facts);
+        }
+        self.segments = manifest.segments;
+
+        let listed: Vec<_> = self
+            .segments
+            .iter()
+            .map(|s| self.dir.join(&s.file))
+            .collect();
+        self.pending.retain(|path| listed.contains(path));
+        Ok(())
+    }
+
+    fn active(&self) -> &SegmentMeta {
+        // `open` guarantees there is always at least one segment
+        &self.segments[self.segments.len() - 1]
+    }
+
+    fn active_mut(&mut self) -> &mut SegmentMeta {
+        let last = self.segments.len() - 1;
+        &mut self.segments[last]
+    }
+
+    /// Replace the manifest atomically: write a temporary file, then rename.
+    fn save(&self) -> io::Result<()> {
+        let manifest = Manifest {
+            segments: self.segments.clone(),
+        };
+        let temporary = self.dir.join(format!("{MANIFEST}.tmp"));
+
+        let mut file = File::create(&temporary)?;
+        serde_json::to_writer_pretty(&mut file, &manifest)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+
+        fs::rename(&temporary, self.dir.join(MANIFEST))
+    }
+}
+
+impl SegmentMeta {
+    fn active(file: String, first: Option<DateTime<Utc>>) -> Self {
+        Self {
+            file,
+            first,
+            last: None,
+            len: None,
+            facts: None,
+        }
+    }
+}
+
+/// Compress the sealed segments queued in `log`, swapping each one for its
+/// compressed copy in the manifest.
+///
+/// The slow part runs without holding `log`, which is only locked to update
+/// the manifest, so appends and readers are not held up by it. On failure,
+/// the segment that failed and those not reached yet stay queued.
+#[cfg(feature = "compression")]
+pub(crate) fn compress_sealed<C: Codec>(
+    log: &parking_lot::Mutex<SegmentLog<C>>,
+) -> Result<(), WriteError> {
+    let Some((plain, level)) = log.lock().take_pending() else {
+        return Ok(());
+    };
+    let mut plain = plain.into_iter();
+    while let Some(path) = plain.next() {
+        let result = match compression::compress_file(&path, level) {
+            Ok(compressed) => log.lock().replace_with_compressed(&path, &compressed),
+            // Another process may have compressed it first
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut log = log.lock();
+                match log.reload() {
+                    Ok(()) if !log.lists(&path) => Ok(()),
+                    Ok(()) => Err(e.into()),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            log.lock().requeue(std::iter::once(path).chain(plain));
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn segment_name(sequence: usize) -> String {
+    format!("{sequence:010}.{SEGMENT_EXTENSION}")
+}
+
+/// Sequence number of the segment file at `path`, compressed or not.
+pub(crate) fn sequence(path: &Path) -> Option<usize> {
+    let name = file_name(path);
+    let name = name.strip_suffix(".zst").unwrap_or(&name);
+    name.strip_suffix(&format!(".{SEGMENT_EXTENSION}"))?
+        .parse()
+        .ok()
+}
+
+/// The segment after the one at `path`, if it has been started.
+pub(crate) fn next_segment(path: &Path) -> Option<PathBuf> {
+    let next = path.with_file_name(segment_name(sequence(path)? + 1));
+    next.exists().then_some(next)
+}
+
+/// Remove the file at `path`, unless someone else already has.
+#[cfg(feature = "compression")]
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Whether the segment file `name` is compressed.
+fn is_compressed_name(name: &str) -> bool {
+    name.ends_with(".zst")
+}
+
+/// Whether `path` is a segment file, compressed or not.
+///
+/// Compressed segments are recognised even without the `compression`
+/// feature, so that reading them fails loudly instead of skipping them.
+fn is_segment(path: &Path) -> bool {
+    let name = file_name(path);
+    name.ends_with(&format!(".{SEGMENT_EXTENSION}"))
+        || name.ends_with(&format!(".{SEGMENT_EXTENSION}.zst"))
+}
+
+/// Recreate the manifest from the segment files found in `dir`.
+fn rebuild_manifest<C: Codec>(codec: &C, dir: &Path) -> io::Result<Manifest> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_segment(&path) {
+            files.push(path);
+        }
+    }
+    // Names are zero-padded sequence numbers, so they sort in order, each
+    // plain segment right before a compressed copy of it
+    files.sort();
+    // A crash while compressing can leave both; the plain one is complete
+    files.dedup_by(|compressed, plain| {
+        file_name(compressed).starts_with(&format!("{}.", file_name(plain)))
+    });
+
+    let mut segments = Vec::new();
+    for (i, path) in files.iter().enumerate() {
+        let bounds = timestamp_bounds(codec, path)?;
+        let mut segment = SegmentMeta::active(file_name(path), bounds.map(|(first, _)| first));
+        if i + 1 < files.len() {
+            segment.last = bounds.map(|(_, last)| last);
+            segment.len = Some(fs::metadata(path)?.len());
+            segment.facts = Some(common::count_facts(codec, path, 0, u64::MAX)?);
+        }
+        segments.push(segment);
+    }
+
+    Ok(Manifest { segments })
+}
+
+/// First and last fact timestamp in a segment file.
+fn timestamp_bounds<C: Codec>(
+    codec: &C,
+    path: &Path,
+) -> io::Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+    let mut file = File::open(path)?;
+    if compression::is_compressed(&mut file)? {
+        return scan_timestamp_bounds(codec, StreamFile::from_file(file)?);
+    }
+
+    let last = common::read_last_line(&mut file, C::DELIMITER)?
+        .and_then(|(_, line)| common::parse_timestamps(codec, &line))
+        .map(|(_, last)| last);
+
+    let mut scanner = LineScanner::new(BufReader::new(File::open(path)?), C::DELIMITER, 0);
+    let mut first = None;
+    while let Some((_, line)) = scanner.next_line()? {
+        if let Some((timestamp, _)) = common::parse_timestamps(codec, line) {
+            first = Some(timestamp);
+            break;
+        }
+    }
+
+    Ok(first.zip(last))
+}
+
+/// First and last fact timestamp in a stream that can only be read forwards.
+fn scan_timestamp_bounds<C: Codec>(
+    codec: &C,
+    file: StreamFile,
+) -> io::Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+    let mut scanner = LineScanner::new(BufReader::new(file), C::DELIMITER, 0);
+    let mut bounds: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    while let Some((_, line)) = scanner.next_line()? {
+        if let Some((first, last)) = common::parse_timestamps(codec, line) {
+            bounds = Some((bounds.map_or(first, |(first, _)| first), last));
+        }
+    }
+    Ok(bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{FactStreamWriter, JsonCodec};
+    use crate::testing::{at, item_facts};
+    use tempfile::TempDir;
+
+    /// Append one fact per second through `log`, like `FactStore` does.
+    fn append(log: &mut SegmentLog<JsonCodec>, seconds: std::ops::Range<i64>) {
+        for fact in item_facts(seconds) {
+            let (path, _lock) = log
+                .prepare_append(*fact.timestamp(), *fact.timestamp())
+                .unwrap();
+            let mut writer = FactStreamWriter::open(path).unwrap();
+            writer.write_batch(&[fact]).unwrap();
+        }
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn rolls_when_segment_reaches_max_bytes() {
+        let dir = TempDir::new().unwrap();
+        // Each fact line is 53 bytes, so three fit before the limit is reached
+        let mut log = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+
+        append(&mut log, 0..9);
+
+        assert_eq!(log.segments.len(), 3);
+        assert_eq!(log.segments[0].first, Some(at(0)));
+        assert_eq!(log.segments[0].last, Some(at(2)));
+        assert_eq!(log.segments[1].first, Some(at(3)));
+        assert_eq!(log.segments[2].last, None);
+    }
+
+    #[test]
+    fn rolls_when_batch_exceeds_max_span() {
+        let dir = TempDir::new().unwrap();
+        let policy = SegmentPolicy::default().with_max_span(Duration::seconds(4));
+        let mut log = SegmentLog::open(dir.path(), policy, JsonCodec).unwrap();
+
+        append(&mut log, 0..12);
+
+        let bounds: Vec<_> = log
+            .segments
+            .iter()
+            .map(|segment| (segment.first, segment.last))
+            .collect();
+        assert_eq!(
+            bounds,
+            vec![
+                (Some(at(0)), Some(at(4))),
+                (Some(at(5)), Some(at(9))),
+                (Some(at(10)), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn files_from_skips_segments_before_since() {
+        let dir = TempDir::new().unwrap();
+        let mut log = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        append(&mut log, 0..9);
+
+        assert_eq!(log.files_from(at(0)).len(), 3);
+        assert_eq!(
+            names(&log.files_from(at(4))),
+            vec!["0000000001.facts", "0000000002.facts"]
+        );
+        assert_eq!(names(&log.files_from(at(100))), vec!["0000000002.facts"]);
+    }
+
+    #[test]
+    fn files_from_seq_finds_segment_holding_fact() {
+        let dir = TempDir::new().unwrap();
+        let mut log = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        append(&mut log, 0..9);
+
+        let facts: Vec<_> = log.segments.iter().map(|segment| segment.facts).collect();
+        assert_eq!(facts, vec![Some(3), Some(3), None]);
+
+        let (files, base) = log.files_from_seq(4).unwrap();
+        assert_eq!(names(&files), vec!["0000000001.facts", "0000000002.facts"]);
+        assert_eq!(base, 3);
+        let (files, base) = log.files_from_seq(100).unwrap();
+        assert_eq!(names(&files), vec!["0000000002.facts"]);
+        assert_eq!(base, 6);
+        assert_eq!(log.first_seq(&log.active_path()).unwrap(), 6);
+    }
+
+    #[test]
+    fn manifest_survives_reopen_and_is_rebuilt_when_missing() {
+        let dir = TempDir::new().unwrap();
+        let mut log = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        append(&mut log, 0..9);
+        let segments = log.segments.clone();
+
+        let reopened =
+            SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        assert_eq!(reopened.segments, segments);
+
+        fs::remove_file(dir.path().join(MANIFEST)).unwrap();
+        let rebuilt = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        assert_eq!(rebuilt.segments, segments);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn sealed_segments_are_compressed_and_rebuilt() {
+        let dir = TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(150).with_compression(3);
+        let mut log = SegmentLog::open(dir.path(), policy, JsonCodec).unwrap();
+        append(&mut log, 0..9);
+
+        // Rolling only queues the sealed segments
+        assert_eq!(log.files_from(at(0)).len(), 3);
+        assert!(dir.path().join("0000000000.facts").exists());
+        assert_eq!(log.pending.len(), 2);
+
+        let log = parking_lot::Mutex::new(log);
+        compress_sealed(&log).unwrap();
+        let log = log.into_inner();
+        assert!(log.pending.is_empty());
+        assert_eq!(
+            names(&log.files_from(at(0))),
+            vec![
+                "0000000000.facts.zst",
+                "0000000001.facts.zst",
+                "0000000002.facts"
+            ]
+        );
+        assert!(!dir.path().join("0000000000.facts").exists());
+        let segments = log.segments.clone();
+
+        fs::remove_file(dir.path().join(MANIFEST)).unwrap();
+        let rebuilt = SegmentLog::open(dir.path(), policy, JsonCodec).unwrap();
+        assert_eq!(rebuilt.segments, segments);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn segments_that_fail_to_compress_stay_queued() {
+        let dir = TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(150).with_compression(3);
+        let log = SegmentLog::open(dir.path(), policy, JsonCodec).unwrap();
+        let log = parking_lot::Mutex::new(log);
+        append(&mut log.lock(), 0..12);
+
+        // Nothing can be written where the second compressed copy goes
+        let blocker = dir
+            .path()
+            .join(format!("0000000001.facts.zst.{}.tmp", std::process::id()));
+        fs::create_dir(&blocker).unwrap();
+        assert!(compress_sealed(&log).is_err());
+        assert_eq!(
+            names(&log.lock().pending),
+            vec!["0000000001.facts", "0000000002.facts"]
+        );
+
+        fs::remove_dir(&blocker).unwrap();
+        compress_sealed(&log).unwrap();
+        assert!(log.lock().pending.is_empty());
+        assert_eq!(
+            names(&log.lock().files_from(at(0))),
+            vec![
+                "0000000000.facts.zst",
+                "0000000001.facts.zst",
+                "0000000002.facts.zst",
+                "0000000003.facts"
+            ]
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn uncompressed_sealed_segments_are_queued_on_open() {
+        let dir = TempDir::new().unwrap();
+        let mut log = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        append(&mut log, 0..9);
+        assert!(log.pending.is_empty());
+
+        let policy = SegmentPolicy::by_size(150).with_compression(3);
+        let log = SegmentLog::open(dir.path(), policy, JsonCodec).unwrap();
+        assert_eq!(
+            names(&log.pending),
+            vec!["0000000000.facts", "0000000001.facts"]
+        );
+
+        let log = parking_lot::Mutex::new(log);
+        compress_sealed(&log).unwrap();
+        let reopened = SegmentLog::open(dir.path(), policy, JsonCodec).unwrap();
+        assert!(reopened.pending.is_empty());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn rebuild_prefers_plain_segment_left_by_interrupted_compression() {
+        let dir = TempDir::new().unwrap();
+        let mut log = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+        append(&mut log, 0..5);
+        let sealed = dir.path().join("0000000000.facts");
+        compression::compress_file(&sealed, 3).unwrap();
+
+        fs::remove_file(dir.path().join(MANIFEST)).unwrap();
+        let rebuilt = SegmentLog::open(dir.path(), SegmentPolicy::by_size(150), JsonCodec).unwrap();
+
+        assert_eq!(
+            names(&rebuilt.files_from(at(0))),
+            vec!["0000000000.facts", "0000000001.facts"]
+        );
+    }
+}
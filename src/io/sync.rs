@@ -1,18 +1,56 @@
 // stainless_facts/src/io/sync.rs
 
-use super::{common, ReadError, WriteError};
+use super::{
+    common::{self, LineFormat},
+    compression::StreamFile,
+    Codec, DurabilityPolicy, JsonCodec, ReadError, SyncSchedule, WriteError,
+};
 use crate::Fact;
 use fs2::FileExt;
 use serde::{de::DeserializeOwned, Serialize};
+use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
 use std::path::Path;
 use std::time::{Duration, Instant};
 
-pub struct FactStreamWriter {
+/// How long writers wait before trying to take a held lock again.
+pub(crate) const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Acquire an exclusive lock on `file`, retrying every `retry_interval`
+/// until `timeout` has passed.
+pub(crate) fn lock_exclusive(
+    file: &File,
+    timeout: Duration,
+    retry_interval: Duration,
+) -> Result<(), WriteError> {
+    let start = Instant::now();
+
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(()),
+            Err(_) if timeout.is_zero() => {
+                return Err(WriteError::AlreadyLocked);
+            }
+            Err(_) if start.elapsed() >= timeout => {
+                return Err(WriteError::LockTimeout(timeout));
+            }
+            Err(_) => {
+                std::thread::sleep(retry_interval);
+            }
+        }
+    }
+}
+
+pub struct FactStreamWriter<C = JsonCodec> {
     file: File,
     writer: BufWriter<File>,
     lock_timeout: Duration,
+    retry_interval: Duration,
+    format: LineFormat,
+    codec: C,
+    schedule: SyncSchedule,
 }
 
 impl FactStreamWriter {
@@ -29,58 +67,179 @@ impl FactStreamWriter {
         path: impl AsRef<Path>,
         timeout: Duration,
     ) -> Result<Self, WriteError> {
-        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
         let writer = BufWriter::new(file.try_clone()?);
         Ok(Self {
             file,
             writer,
             lock_timeout: timeout,
+            retry_interval: LOCK_RETRY_INTERVAL,
+            format: LineFormat::default(),
+            codec: JsonCodec,
+            schedule: SyncSchedule::new(DurabilityPolicy::default()),
         })
     }
+}
 
-    /// Acquire exclusive lock with configured timeout
-    fn acquire_lock(&self) -> Result<(), WriteError> {
-        let start = Instant::now();
-        let retry_interval = Duration::from_millis(100);
+impl<C: Codec> FactStreamWriter<C> {
+    /// Encode records with `codec` instead of JSON.
+    ///
+    /// Readers of the stream must use the same codec.
+    pub fn with_codec<D: Codec>(self, codec: D) -> FactStreamWriter<D> {
+        FactStreamWriter {
+            file: self.file,
+            writer: self.writer,
+            lock_timeout: self.lock_timeout,
+            retry_interval: self.retry_interval,
+            format: self.format,
+            codec,
+            schedule: self.schedule,
+        }
+    }
 
-        loop {
-            match self.file.try_lock_exclusive() {
-                Ok(()) => return Ok(()),
-                Err(_) if self.lock_timeout.is_zero() => {
-                    return Err(WriteError::AlreadyLocked);
-                }
-                Err(_) if start.elapsed() >= self.lock_timeout => {
-                    return Err(WriteError::LockTimeout(self.lock_timeout));
-                }
-                Err(_) => {
-                    std::thread::sleep(retry_interval);
-                }
-            }
+    /// Write each batch as a single framed record.
+    ///
+    /// A framed batch is one line that readers see either completely or not
+    /// at all, so a crash part way through a write can never expose half a
+    /// batch. Framed and unframed batches can be mixed in the same stream.
+    pub fn with_batch_framing(mut self, enabled: bool) -> Self {
+        self.format.framed = enabled;
+        self
+    }
+
+    /// Append a CRC32C checksum to every line written.
+    ///
+    /// Readers verify the checksum of every line that has one and report a
+    /// mismatch as [`ReadError::ChecksumMismatch`]. Lines without a checksum
+    /// are still accepted, so existing streams stay readable.
+    pub fn with_checksums(mut self, enabled: bool) -> Self {
+        self.format.checksums = enabled;
+        self
+    }
+
+    /// Fsync written batches according to `policy` instead of after each one.
+    ///
+    /// See [`DurabilityPolicy`] for what each policy can lose in a crash.
+    pub fn with_durability(mut self, policy: DurabilityPolicy) -> Self {
+        self.schedule = SyncSchedule::new(policy);
+        self
+    }
+
+    pub(crate) fn with_format(mut self, format: LineFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Wait up to `timeout` for the lock, trying every `retry_interval`.
+    pub(crate) fn with_lock_wait(mut self, timeout: Duration, retry_interval: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Carry on with a schedule kept by a store across writers.
+    pub(crate) fn with_schedule(mut self, schedule: SyncSchedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    pub(crate) fn schedule(&self) -> SyncSchedule {
+        self.schedule
+    }
+
+    /// Force everything written so far to stable storage.
+    ///
+    /// Under any [`DurabilityPolicy`] but `Always`, call this before relying
+    /// on the last batches surviving an operating system crash.
+    pub fn flush_durable(&mut self) -> Result<(), WriteError> {
+        self.writer.flush()?;
+        self.file.sync_all()?;
+        self.schedule.synced();
+        Ok(())
+    }
+
+    /// Record `batches` just written, and fsync them if the policy says so.
+    pub(crate) fn written(&mut self, batches: usize) -> Result<(), WriteError> {
+        if self.schedule.written(batches) {
+            self.flush_durable()?;
         }
+        Ok(())
+    }
+
+    /// Acquire exclusive lock with configured timeout
+    fn acquire_lock(&self) -> Result<(), WriteError> {
+        lock_exclusive(&self.file, self.lock_timeout, self.retry_interval)
     }
 
     /// Write a batch of facts atomically.
     ///
     /// Acquires exclusive lock, writes all facts, then releases lock.
     /// All facts are serialized to memory first. If serialization fails,
-    /// no facts are written. After successful write, fsync ensures durability.
+    /// no facts are written. After a successful write, the batch is synced
+    /// to disk according to the writer's [`DurabilityPolicy`].
     pub fn write_batch<E, V, S>(&mut self, facts: &[Fact<E, V, S>]) -> Result<(), WriteError>
     where
         E: Serialize,
         V: Serialize,
         S: Serialize,
     {
-        let buffer = common::serialize_batch(facts)?;
+        self.append_batch(facts, |_| Ok::<_, WriteError>(()))
+            .map(|_| ())
+    }
 
+    /// Write a batch of facts atomically, returning the byte range it took.
+    ///
+    /// `check` is called with the file while the exclusive lock is held and
+    /// before anything is written, so it sees everything other writers have
+    /// committed; if it fails, the batch is not written. The file length is
+    /// taken under the same lock, so the range is exactly where this batch
+    /// ended up.
+    pub(crate) fn append_batch<E, V, S, X>(
+        &mut self,
+        facts: &[Fact<E, V, S>],
+        check: impl FnOnce(&mut File) -> Result<(), X>,
+    ) -> Result<Range<u64>, X>
+    where
+        E: Serialize,
+        V: Serialize,
+        S: Serialize,
+        X: From<WriteError>,
+    {
+        let buffer =
+            common::serialize_batch(&self.codec, facts, self.format).map_err(WriteError::from)?;
+        let written = self.append_with(|file| check(file).map(|()| buffer))?;
+        self.written(1)?;
+        Ok(written)
+    }
+
+    /// Write the bytes returned by `prepare` under the exclusive lock.
+    ///
+    /// `prepare` is called with the file once the lock is held, and decides
+    /// what to write from what it finds there. The bytes are handed to the
+    /// operating system but not synced; see [`FactStreamWriter::written`].
+    pub(crate) fn append_with<X>(
+        &mut self,
+        prepare: impl FnOnce(&mut File) -> Result<Vec<u8>, X>,
+    ) -> Result<Range<u64>, X>
+    where
+        X: From<WriteError>,
+    {
         // Acquire lock only for the duration of the write
         self.acquire_lock()?;
 
-        let result = (|| {
-            self.writer.write_all(&buffer)?;
-            self.writer.flush()?;
-            self.file.sync_all()?;
-            Ok(())
-        })();
+        let result = prepare(&mut self.file).and_then(|buffer| {
+            (|| {
+                let start = self.file.metadata()?.len();
+                self.writer.write_all(&buffer)?;
+                self.writer.flush()?;
+                Ok(start..start + buffer.len() as u64)
+            })()
+            .map_err(|e: std::io::Error| X::from(e.into()))
+        });
 
         // Always release lock, even on error
         let _ = FileExt::unlock(&self.file);
@@ -89,15 +248,20 @@ impl FactStreamWriter {
     }
 }
 
-pub struct FactStreamReader<E, V, S> {
-    reader: BufReader<File>,
-    _phantom: std::marker::PhantomData<(E, V, S)>,
+pub struct FactStreamReader<E, V, S, C = JsonCodec> {
+    reader: BufReader<StreamFile>,
+    line: Vec<u8>,
+    /// Facts decoded from a framed batch that have not been yielded yet
+    pending: VecDeque<Fact<E, V, S>>,
+    codec: C,
 }
 
 impl<E, V, S> FactStreamReader<E, V, S> {
     /// Open a fact stream file for reading.
     ///
-    /// Acquires a shared lock immediately or fails.
+    /// Acquires a shared lock immediately or fails. A zstd-compressed file,
+    /// such as a sealed segment of a compressed store, is decompressed
+    /// transparently when the `compression` feature is enabled.
     pub fn open(path: impl AsRef<Path>) -> Result<Self, ReadError> {
         Self::open_with_timeout(path, Duration::from_secs(0))
     }
@@ -112,10 +276,12 @@ impl<E, V, S> FactStreamReader<E, V, S> {
         loop {
             match FileExt::try_lock_shared(&file) {
                 Ok(()) => {
-                    let reader = BufReader::new(file);
+                    let reader = BufReader::new(StreamFile::from_file(file)?);
                     return Ok(Self {
                         reader,
-                        _phantom: std::marker::PhantomData,
+                        line: Vec::new(),
+                        pending: VecDeque::new(),
+                        codec: JsonCodec,
                     });
                 }
                 Err(_) if timeout.is_zero() => {
@@ -132,8 +298,21 @@ impl<E, V, S> FactStreamReader<E, V, S> {
     }
 }
 
-impl<E, V, S> Iterator for FactStreamReader<E, V, S>
+impl<E, V, S, C: Codec> FactStreamReader<E, V, S, C> {
+    /// Decode records with `codec` instead of JSON.
+    pub fn with_codec<D: Codec>(self, codec: D) -> FactStreamReader<E, V, S, D> {
+        FactStreamReader {
+            reader: self.reader,
+            line: self.line,
+            pending: self.pending,
+            codec,
+        }
+    }
+}
+
+impl<E, V, S, C> Iterator for FactStreamReader<E, V, S, C>
 where
+    C: Codec,
     E: DeserializeOwned,
     V: DeserializeOwned,
     S: DeserializeOwned,
@@ -141,21 +320,25 @@ where
     type Item = Result<Fact<E, V, S>, ReadError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut line = String::new();
-
         loop {
-            line.clear();
-            match self.reader.read_line(&mut line) {
+            if let Some(fact) = self.pending.pop_front() {
+                return Some(Ok(fact));
+            }
+
+            self.line.clear();
+            match self.reader.read_until(C::DELIMITER, &mut self.line) {
                 Ok(0) => return None, // EOF
                 Ok(_) => {
-                    let trimmed = line.trim();
-                    if trimmed.is_empty() {
+                    if common::is_blank(&self.line) {
                         continue; // Skip empty lines
                     }
 
-                    match serde_json::from_str(trimmed) {
-                        Ok(fact) => return Some(Ok(fact)),
-                        Err(e) => return Some(Err(ReadError::Deserialization(e))),
+                    if let Err(e) = common::decode_line(&self.codec, &self.line, &mut self.pending)
+                    {
+                        if self.line.last() != Some(&C::DELIMITER) {
+                            return None; // A write still in progress
+                        }
+                        return Some(Err(e));
                     }
                 }
                 Err(e) => return Some(Err(ReadError::Io(e))),
@@ -40,6 +40,33 @@ mod tests {
         assert_eq!(facts[0], original);
     }
 
+    #[test]
+    fn sync_writer_with_relaxed_durability() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts: Vec<_> = (0..5)
+            .map(|i| {
+                Fact::new(
+                    format!("track{i}"),
+                    TestValue::Bpm(12800 + i),
+                    "2024-01-15T10:00:00Z".parse().unwrap(),
+                    "alice".to_string(),
+                    Operation::Assert,
+                )
+            })
+            .collect();
+
+        let mut writer = FactStreamWriter::open(temp.path())
+            .unwrap()
+            .with_durability(DurabilityPolicy::OsBuffered);
+        for fact in &facts {
+            writer.write_batch(&[fact.clone()]).unwrap();
+        }
+        writer.flush_durable().unwrap();
+
+        let read: Result<Vec<_>, _> = FactStreamReader::open(temp.path()).unwrap().collect();
+        assert_eq!(read.unwrap(), facts);
+    }
+
     #[test]
     fn sync_round_trip_multiple_facts() {
         let temp = NamedTempFile::new().unwrap();
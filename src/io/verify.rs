@@ -0,0 +1,251 @@
+// stainless_facts/src/io/verify.rs
+//
+// Integrity check of a whole fact stream.
+
+use super::{common, compression, Codec, ReadError};
+use crate::Fact;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// What [`FactStore::verify`](crate::FactStore::verify) found in a fact stream.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Non-blank lines examined
+    pub lines: u64,
+    /// Facts that were read successfully
+    pub facts: u64,
+    /// Lines that carried a checksum, whether or not it matched
+    pub checksummed_lines: u64,
+    /// Every problem found, in file order
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    /// Whether no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// A problem found at a specific line of a fact stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyIssue {
+    /// File the line is in; one of the segments for a segmented store
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: u64,
+    /// Byte offset of the start of the line
+    pub offset: u64,
+    pub kind: VerifyIssueKind,
+}
+
+/// The kinds of problem [`FactStore::verify`](crate::FactStore::verify) reports.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyIssueKind {
+    /// The line's contents no longer hash to its checksum.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// The line is not a fact or a framed batch of facts.
+    Unparseable(String),
+    /// A fact is older than the one before it.
+    OutOfOrder {
+        timestamp: DateTime<Utc>,
+        previous: DateTime<Utc>,
+    },
+    /// The last line has no terminating newline.
+    Incomplete,
+}
+
+/// Walk every line of the stream stored in `files` and report what is wrong.
+///
+/// The files are checked in order as one stream, so timestamp ordering is
+/// also checked across them. Unlike the store iterators, this keeps going
+/// past damaged lines. Missing files are treated as empty.
+pub(crate) fn verify<C, E, V, S>(codec: &C, files: &[PathBuf]) -> io::Result<VerifyReport>
+where
+    C: Codec,
+    E: DeserializeOwned,
+    V: DeserializeOwned,
+    S: DeserializeOwned,
+{
+    let mut report = VerifyReport::default();
+    let mut previous = None;
+
+    for path in files {
+        verify_file::<C, E, V, S>(codec, path, &mut previous, &mut report)?;
+    }
+
+    Ok(report)
+}
+
+fn verify_file<C, E, V, S>(
+    codec: &C,
+    path: &Path,
+    previous: &mut Option<DateTime<Utc>>,
+    report: &mut VerifyReport,
+) -> io::Result<()>
+where
+    C: Codec,
+    E: DeserializeOwned,
+    V: DeserializeOwned,
+    S: DeserializeOwned,
+{
+    let (path, file) = match compression::open_segment(path) {
+        Ok(opened) => opened,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::with_capacity(1024);
+    let mut facts = VecDeque::<Fact<E, V, S>>::new();
+    let mut offset = 0;
+    let mut line_number = 0;
+
+    loop {
+        line.clear();
+        let read = reader.read_until(C::DELIMITER, &mut line)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        let mut issue = |kind| {
+            report.issues.push(VerifyIssue {
+                path: path.clone(),
+                line: line_number,
+                offset,
+                kind,
+            })
+        };
+
+        if !common::is_blank(&line) {
+            report.lines += 1;
+            if common::has_checksum(codec, &line) {
+                report.checksummed_lines += 1;
+            }
+
+            if line.last() != Some(&C::DELIMITER) {
+                issue(VerifyIssueKind::Incomplete);
+            } else {
+                match common::decode_line(codec, &line, &mut facts) {
+                    Ok(()) => {}
+                    Err(ReadError::ChecksumMismatch { expected, actual }) => {
+                        issue(VerifyIssueKind::ChecksumMismatch { expected, actual })
+                    }
+                    Err(e) => issue(VerifyIssueKind::Unparseable(e.to_string())),
+                }
+
+                for fact in facts.drain(..) {
+                    let timestamp = *fact.timestamp();
+                    match *previous {
+                        Some(previous) if timestamp < previous => {
+                            issue(VerifyIssueKind::OutOfOrder {
+                                timestamp,
+                                previous,
+                            })
+                        }
+                        _ => *previous = Some(timestamp),
+                    }
+                    report.facts += 1;
+                }
+            }
+        }
+
+        offset += read as u64;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{FactStreamWriter, JsonCodec};
+    use crate::testing::{append_garbage, at, item_facts};
+    use tempfile::TempDir;
+
+    fn write_facts(path: &Path, seconds: impl IntoIterator<Item = i64>, checksums: bool) {
+        let mut writer = FactStreamWriter::open(path)
+            .unwrap()
+            .with_checksums(checksums);
+        writer.write_batch(&item_facts(seconds)).unwrap();
+    }
+
+    fn kinds(report: &VerifyReport) -> Vec<(u64, &VerifyIssueKind)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.line, &issue.kind))
+            .collect()
+    }
+
+    #[test]
+    fn healthy_stream_has_no_issues() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        write_facts(&path, 0..3, false);
+        write_facts(&path, 3..6, true);
+
+        let report =
+            verify::<_, String, i64, String>(&JsonCodec, std::slice::from_ref(&path)).unwrap();
+
+        assert!(report.is_ok());
+        assert_eq!(report.lines, 6);
+        assert_eq!(report.facts, 6);
+        assert_eq!(report.checksummed_lines, 3);
+    }
+
+    #[test]
+    fn reports_every_problem_with_its_position() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        write_facts(&path, 0..2, true);
+        let damaged_offset = std::fs::metadata(&path).unwrap().len();
+        write_facts(&path, 2..3, true);
+        append_garbage(&path, b"not a fact\n");
+        write_facts(&path, [0], false);
+        append_garbage(&path, b"[\"item9\"");
+
+        // Edit the value of the third fact behind the checksum's back
+        let mut contents = std::fs::read(&path).unwrap();
+        let value = damaged_offset as usize + b"[\"item2\",".len();
+        contents[value] = b'7';
+        std::fs::write(&path, contents).unwrap();
+
+        let report =
+            verify::<_, String, i64, String>(&JsonCodec, std::slice::from_ref(&path)).unwrap();
+
+        let issues = kinds(&report);
+        assert_eq!(issues.len(), 4);
+        assert!(matches!(
+            issues[0],
+            (3, VerifyIssueKind::ChecksumMismatch { .. })
+        ));
+        assert_eq!(report.issues[0].offset, damaged_offset);
+        assert!(matches!(issues[1], (4, VerifyIssueKind::Unparseable(_))));
+        assert_eq!(
+            issues[2],
+            (
+                5,
+                &VerifyIssueKind::OutOfOrder {
+                    timestamp: at(0),
+                    previous: at(1),
+                }
+            )
+        );
+        assert_eq!(issues[3], (6, &VerifyIssueKind::Incomplete));
+        assert_eq!(report.facts, 3);
+    }
+
+    #[test]
+    fn missing_stream_is_empty() {
+        let dir = TempDir::new().unwrap();
+
+        let report =
+            verify::<_, String, i64, String>(&JsonCodec, &[dir.path().join("missing")]).unwrap();
+
+        assert_eq!(report, VerifyReport::default());
+    }
+}
@@ -7,18 +7,42 @@
 //! feature for async I/O with `AsyncFactStore`.
 
 // Sync I/O - always available
+mod aggregation;
+mod cursor;
+mod follow;
 pub mod io;
+mod options;
+mod snapshot;
 pub mod store;
-
-pub use io::{FactStreamReader, FactStreamWriter, ReadError, WriteError};
-pub use store::{FactIterator, FactStore, StoreError};
+#[cfg(test)]
+mod testing;
+
+pub use aggregation::Aggregation;
+pub use cursor::Cursor;
+pub use follow::{FollowHandle, FollowIterator};
+#[cfg(feature = "msgpack")]
+pub use io::MessagePackCodec;
+pub use io::{
+    Codec, CodecError, DurabilityPolicy, FactStreamReader, FactStreamWriter, JsonCodec, ReadError,
+    RecoveryPolicy, RecoveryReport, SegmentPolicy, VerifyIssue, VerifyIssueKind, VerifyReport,
+    WriteError,
+};
+pub use options::{FactStoreOptions, OpenMode, OrderingPolicy};
+pub use snapshot::{Snapshot, SnapshotError, SnapshotStore};
+pub use store::{FactIterator, FactStore, StoreError, TryFactIterator};
 
 // Async I/O - only with tokio feature
 #[cfg(feature = "tokio")]
 mod async_store;
+#[cfg(feature = "tokio")]
+mod group_commit;
+#[cfg(feature = "tokio")]
+mod subscription;
 
 #[cfg(feature = "tokio")]
-pub use async_store::{AsyncFactIterator, AsyncFactStore};
+pub use async_store::{AsyncFactIterator, AsyncFactStore, AsyncFactWatch, AsyncTryFactIterator};
+#[cfg(feature = "tokio")]
+pub use subscription::{CommittedBatch, Subscription, SubscriptionError};
 
 // Core types
 use chrono::{DateTime, Utc};
@@ -112,17 +136,29 @@ where
     let mut aggregators = HashMap::new();
 
     for fact in facts {
-        let aggregator: &mut A = aggregators.entry(fact.entity().clone()).or_default();
-
-        match fact.operation() {
-            Operation::Assert => aggregator.assert(fact.value(), fact.source()),
-            Operation::Retract => aggregator.retract(fact.value(), fact.source()),
-        }
+        apply_fact(&mut aggregators, &fact);
     }
 
     aggregators
 }
 
+/// Apply one fact to the aggregator of its entity.
+pub(crate) fn apply_fact<E, V, S, A>(aggregators: &mut HashMap<E, A>, fact: &Fact<E, V, S>)
+where
+    E: Eq + Hash + Clone,
+    A: FactAggregator<E, V, S> + Default,
+{
+    let aggregator = match aggregators.get_mut(fact.entity()) {
+        Some(aggregator) => aggregator,
+        None => aggregators.entry(fact.entity().clone()).or_default(),
+    };
+
+    match fact.operation() {
+        Operation::Assert => aggregator.assert(fact.value(), fact.source()),
+        Operation::Retract => aggregator.retract(fact.value(), fact.source()),
+    }
+}
+
 /// Trait for building validated output from an aggregator.
 pub trait Buildable {
     type Output;
@@ -0,0 +1,233 @@
+// stainless-facts: options for opening stores
+// ===========================================
+//
+// One builder for how FactStore and AsyncFactStore open their file and
+// append to it.
+
+use crate::io::{Codec, DurabilityPolicy, JsonCodec, RecoveryPolicy, LOCK_RETRY_INTERVAL};
+use crate::store::StoreError;
+use crate::Fact;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Whether opening a store may create it, and whether it may be written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Create the store, and the directories above it, if it does not exist.
+    #[default]
+    Create,
+    /// Fail with [`std::io::ErrorKind::NotFound`] if the store does not exist.
+    MustExist,
+    /// Open an existing store for reading only.
+    ///
+    /// Nothing is written, not even by crash recovery, which is skipped:
+    /// readers stop before an incomplete last record anyway. Appends fail
+    /// with [`StoreError::ReadOnly`]. Like `MustExist`, a missing store is
+    /// an error.
+    ReadOnly,
+}
+
+/// Which timestamps an append accepts, given the facts before it.
+///
+/// Facts are checked against the last fact in the store and against the
+/// facts before them in the same batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrderingPolicy {
+    /// No fact may be older than the one before it; facts may share a
+    /// timestamp.
+    #[default]
+    NonDecreasing,
+    /// Every fact must be newer than the one before it, so no two facts
+    /// share a timestamp.
+    StrictlyIncreasing,
+    /// Accept facts in any order.
+    ///
+    /// Reading from a timestamp relies on the store being ordered, so
+    /// `iter_from` can start past facts that were appended late. Read those
+    /// stores with `iter` and filter, and use `verify` to find facts that
+    /// are out of order.
+    Unchecked,
+}
+
+impl OrderingPolicy {
+    /// Reject `facts` if any of them is out of order after `latest` and the
+    /// facts before it in the batch.
+    pub(crate) fn check<E, V, S>(
+        self,
+        facts: &[Fact<E, V, S>],
+        latest: Option<DateTime<Utc>>,
+    ) -> Result<(), StoreError> {
+        if self == Self::Unchecked {
+            return Ok(());
+        }
+
+        let mut previous = latest;
+        for fact in facts {
+            let new = *fact.timestamp();
+            if let Some(latest) = previous {
+                let ordered = match self {
+                    Self::StrictlyIncreasing => new > latest,
+                    _ => new >= latest,
+                };
+                if !ordered {
+                    return Err(StoreError::TimestampOrdering { new, latest });
+                }
+            }
+            previous = Some(new);
+        }
+        Ok(())
+    }
+}
+
+/// How to open a [`FactStore`](crate::FactStore) or `AsyncFactStore`.
+///
+/// Pass it to `FactStore::open_with` or `AsyncFactStore::open_with`. Every
+/// setting defaults to what `open_or_create` does.
+///
+/// # Example
+///
+/// ```no_run
+/// use stainless_facts::{DurabilityPolicy, FactStore, FactStoreOptions, OpenMode};
+/// use std::time::Duration;
+///
+/// # fn example() -> Result<(), stainless_facts::StoreError> {
+/// let store: FactStore<String, String, String> = FactStore::open_with(
+///     FactStoreOptions::new("data.facts")
+///         .with_mode(OpenMode::MustExist)
+///         .with_lock_timeout(Duration::from_secs(5))
+///         .with_durability(DurabilityPolicy::EveryNBatches(100)),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FactStoreOptions<C = JsonCodec> {
+    pub(crate) path: PathBuf,
+    pub(crate) mode: OpenMode,
+    pub(crate) lock_timeout: Duration,
+    pub(crate) retry_interval: Duration,
+    pub(crate) durability: DurabilityPolicy,
+    pub(crate) ordering: OrderingPolicy,
+    pub(crate) recovery: RecoveryPolicy,
+    pub(crate) codec: C,
+}
+
+impl FactStoreOptions {
+    /// Options for the store at `path`, with every setting at its default.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: OpenMode::default(),
+            lock_timeout: Duration::ZERO,
+            retry_interval: LOCK_RETRY_INTERVAL,
+            durability: DurabilityPolicy::default(),
+            ordering: OrderingPolicy::default(),
+            recovery: RecoveryPolicy::default(),
+            codec: JsonCodec,
+        }
+    }
+}
+
+impl<C: Codec> FactStoreOptions<C> {
+    /// Encode records with `codec` instead of JSON.
+    ///
+    /// See [`FactStore::open_with_codec`](crate::FactStore::open_with_codec).
+    pub fn with_codec<D: Codec>(self, codec: D) -> FactStoreOptions<D> {
+        FactStoreOptions {
+            path: self.path,
+            mode: self.mode,
+            lock_timeout: self.lock_timeout,
+            retry_interval: self.retry_interval,
+            durability: self.durability,
+            ordering: self.ordering,
+            recovery: self.recovery,
+            codec,
+        }
+    }
+
+    /// Whether the store may be created, or written to at all.
+    pub fn with_mode(mut self, mode: OpenMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Wait up to `timeout` for another process's write lock.
+    ///
+    /// By default an append fails at once with
+    /// [`WriteError::AlreadyLocked`](crate::WriteError::AlreadyLocked) while
+    /// another process is writing. With a timeout it retries, and fails
+    /// with [`WriteError::LockTimeout`](crate::WriteError::LockTimeout) if
+    /// the lock is still held when the timeout has passed. Opening waits
+    /// the same way when it has an interrupted write to repair.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = timeout;
+        self
+    }
+
+    /// Try to take a held lock again after `interval` (default 100 ms).
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// Fsync appended batches according to `policy`.
+    ///
+    /// See [`FactStore::with_durability`](crate::FactStore::with_durability).
+    pub fn with_durability(mut self, policy: DurabilityPolicy) -> Self {
+        self.durability = policy;
+        self
+    }
+
+    /// Accept appended timestamps according to `policy`.
+    pub fn with_ordering(mut self, policy: OrderingPolicy) -> Self {
+        self.ordering = policy;
+        self
+    }
+
+    /// Repair an interrupted write found on open with `policy`.
+    ///
+    /// See [`FactStore::open_with_recovery`](crate::FactStore::open_with_recovery).
+    pub fn with_recovery(mut self, policy: RecoveryPolicy) -> Self {
+        self.recovery = policy;
+        self
+    }
+
+    /// Fail with `NotFound` if the store does not exist and may not be created.
+    pub(crate) fn check_exists(&self) -> Result<(), StoreError> {
+        if self.mode == OpenMode::Create || self.path.exists() {
+            return Ok(());
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("fact store {} does not exist", self.path.display()),
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{at, item_facts};
+
+    #[test]
+    fn ordering_policies_check_within_the_batch_too() {
+        let tied = item_facts([1, 1, 2]);
+        let backwards = item_facts([2, 1]);
+
+        let policy = OrderingPolicy::NonDecreasing;
+        assert!(policy.check(&tied, None).is_ok());
+        assert!(policy.check(&tied, Some(at(1))).is_ok());
+        assert!(policy.check(&tied, Some(at(2))).is_err());
+        assert!(policy.check(&backwards, None).is_err());
+
+        let policy = OrderingPolicy::StrictlyIncreasing;
+        assert!(policy.check(&tied, None).is_err());
+        assert!(policy.check(&tied[1..], Some(at(1))).is_err());
+        assert!(policy.check(&tied[1..], Some(at(0))).is_ok());
+
+        let policy = OrderingPolicy::Unchecked;
+        assert!(policy.check(&backwards, Some(at(5))).is_ok());
+    }
+}
@@ -0,0 +1,605 @@
+// stainless-facts: snapshots of aggregated state
+// ===============================================
+//
+// Aggregates saved together with the position in the store they cover, so
+// that rebuilding them only replays the facts appended since.
+
+use crate::{
+    apply_fact,
+    io::Codec,
+    store::{FactStore, StoreError},
+    Cursor, FactAggregator,
+};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Snapshot files are named this, followed by their number.
+const PREFIX: &str = "snapshot-";
+
+const EXTENSION: &str = "json";
+
+/// Snapshots kept by default when a new one is saved.
+const DEFAULT_RETENTION: usize = 3;
+
+/// Layout of snapshot files, recorded in their header.
+const FORMAT: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("Store error: {0}")]
+    Store(#[from] StoreError),
+
+    #[error("Snapshot {} is damaged: {reason}", path.display())]
+    Damaged { path: PathBuf, reason: String },
+}
+
+/// Aggregated state, and the position in a fact store it covers.
+///
+/// The watermark is the [`Cursor`] after the last fact applied to the
+/// state, or `None` if none was. Replaying the facts after it brings the
+/// state up to date, so a service can start from a saved snapshot instead of
+/// the first fact.
+///
+/// # Example
+///
+/// ```no_run
+/// # use stainless_facts::{aggregate_facts, FactStore, Snapshot};
+/// # use std::collections::HashMap;
+/// # #[derive(Default, serde::Serialize, serde::Deserialize)]
+/// # struct Track { plays: u32 }
+/// # impl stainless_facts::FactAggregator<String, String, String> for Track {
+/// #     fn assert(&mut self, _: &String, _: &String) { self.plays += 1 }
+/// #     fn retract(&mut self, _: &String, _: &String) {}
+/// # }
+/// # fn example(store: FactStore<String, String, String>) {
+/// let mut facts = store.iter();
+/// let state: HashMap<String, Track> = aggregate_facts(facts.by_ref());
+/// let snapshot = Snapshot::new(state, facts.cursor());
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Snapshot<E, A> {
+    state: HashMap<E, A>,
+    watermark: Option<Cursor>,
+}
+
+impl<E: Eq + Hash, A: PartialEq> PartialEq for Snapshot<E, A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.state == other.state && self.watermark == other.watermark
+    }
+}
+
+impl<E, A> Default for Snapshot<E, A> {
+    fn default() -> Self {
+        Self {
+            state: HashMap::new(),
+            watermark: None,
+        }
+    }
+}
+
+impl<E, A> Snapshot<E, A> {
+    /// A snapshot of `state`, which covers the facts up to `watermark`.
+    pub fn new(state: HashMap<E, A>, watermark: Option<Cursor>) -> Self {
+        Self { state, watermark }
+    }
+
+    /// The aggregated state.
+    pub fn state(&self) -> &HashMap<E, A> {
+        &self.state
+    }
+
+    /// Take the aggregated state.
+    pub fn into_state(self) -> HashMap<E, A> {
+        self.state
+    }
+
+    /// Position after the last fact the state covers.
+    pub fn watermark(&self) -> Option<Cursor> {
+        self.watermark
+    }
+
+    /// Timestamp of the last fact the state covers.
+    pub fn timestamp(&self) -> Option<DateTime<Utc>> {
+        self.watermark.map(|cursor| cursor.timestamp())
+    }
+}
+
+/// Apply the facts in `store` after the snapshot's watermark to its state,
+/// and move the watermark past them.
+///
+/// Fails with [`StoreError::CursorMismatch`], before applying anything, if
+/// the store no longer holds the fact the watermark points after. If a fact
+/// fails to read, the watermark is left after the last one applied.
+pub(crate) fn replay<E, V, S, C, A>(
+    state: &mut HashMap<E, A>,
+    watermark: &mut Option<Cursor>,
+    store: &FactStore<E, V, S, C>,
+) -> Result<u64, StoreError>
+where
+    E: Serialize + DeserializeOwned + Clone + Eq + Hash,
+    V: Serialize + DeserializeOwned + Clone,
+    S: Serialize + DeserializeOwned + Clone,
+    C: Codec,
+    A: FactAggregator<E, V, S> + Default,
+{
+    let mut facts = match *watermark {
+        Some(cursor) => store.try_iter_from_cursor(cursor),
+        None => store.try_iter(),
+    };
+    let mut applied = 0;
+    let result = facts.by_ref().try_for_each(|fact| {
+        apply_fact(state, &fact?);
+        applied += 1;
+        Ok(())
+    });
+    if applied > 0 {
+        *watermark = facts.cursor();
+    }
+    result.map(|()| applied)
+}
+
+/// Header line of a snapshot file.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: u32,
+    /// Length of the state after the header
+    len: u64,
+    /// CRC32C of the state
+    checksum: u32,
+}
+
+/// The state of a snapshot as it is written.
+#[derive(Serialize)]
+struct StateRef<'a, E, A> {
+    watermark: Option<Cursor>,
+    entries: Vec<(&'a E, &'a A)>,
+}
+
+/// The state of a snapshot as it is read.
+#[derive(Deserialize)]
+struct State<E, A> {
+    watermark: Option<Cursor>,
+    entries: Vec<(E, A)>,
+}
+
+/// A directory of snapshots, newest last.
+///
+/// Each snapshot is a file holding a header with the length and CRC32C
+/// checksum of the state, followed by the state and its watermark as JSON.
+/// Entities are stored as a list of entries rather than a JSON object, so
+/// any serializable entity type works. Snapshots are written under a
+/// temporary name, synced and renamed into place, so a crash while saving
+/// leaves the earlier snapshots as they were.
+///
+/// # Example
+///
+/// ```no_run
+/// # use stainless_facts::{FactStore, SnapshotStore};
+/// # #[derive(Default, serde::Serialize, serde::Deserialize)]
+/// # struct Track { plays: u32 }
+/// # impl stainless_facts::FactAggregator<String, String, String> for Track {
+/// #     fn assert(&mut self, _: &String, _: &String) { self.plays += 1 }
+/// #     fn retract(&mut self, _: &String, _: &String) {}
+/// # }
+/// # fn example(store: FactStore<String, String, String>) -> Result<(), stainless_facts::SnapshotError> {
+/// let snapshots = SnapshotStore::open("snapshots")?;
+///
+/// // The newest snapshot, plus the facts appended since it was saved
+/// let snapshot = snapshots.restore::<_, _, _, _, Track>(&store)?;
+/// snapshots.save(&snapshot)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    retention: usize,
+}
+
+impl SnapshotStore {
+    /// Keep snapshots in the directory `dir`, creating it if needed.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, SnapshotError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            retention: DEFAULT_RETENTION,
+        })
+    }
+
+    /// Keep the `count` newest snapshots when saving one (default 3).
+    ///
+    /// Older ones are deleted once the new one is in place. The snapshot
+    /// just saved is always kept, so `0` behaves like `1`. Keeping more
+    /// than one leaves something to fall back to if the newest is damaged.
+    pub fn with_retention(mut self, count: usize) -> Self {
+        self.retention = count.max(1);
+        self
+    }
+
+    /// Save `snapshot` as the newest, and delete those past the retention.
+    ///
+    /// Returns the path of the new snapshot file.
+    pub fn save<E, A>(&self, snapshot: &Snapshot<E, A>) -> Result<PathBuf, SnapshotError>
+    where
+        E: Serialize,
+        A: Serialize,
+    {
+        let state = serde_json::to_vec(&StateRef {
+            watermark: snapshot.watermark,
+            entries: snapshot.state.iter().collect(),
+        })?;
+        let header = Header {
+            format: FORMAT,
+            len: state.len() as u64,
+            checksum: crc32c::crc32c(&state),
+        };
+
+        let number = self.files()?.last().map_or(1, |(number, _)| number + 1);
+        let path = self.dir.join(format!("{PREFIX}{number:020}.{EXTENSION}"));
+        let temporary = path.with_extension(format!("{EXTENSION}.tmp"));
+
+        let mut file = File::create(&temporary)?;
+        serde_json::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        file.write_all(&state)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
+
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Load the newest snapshot that is intact and holds the requested types.
+    ///
+    /// Snapshots that are damaged, or whose state does not deserialize into
+    /// `E` and `A`, for example after the aggregator changed, are skipped.
+    /// Returns `None` if no snapshot is left. Failing to read a snapshot
+    /// file is an error rather than a reason to skip it.
+    pub fn load_latest<E, A>(&self) -> Result<Option<Snapshot<E, A>>, SnapshotError>
+    where
+        E: DeserializeOwned + Eq + Hash,
+        A: DeserializeOwned,
+    {
+        for (_, path) in self.files()?.iter().rev() {
+            if let Some(snapshot) = Self::load_usable(path)? {
+                return Ok(Some(snapshot));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Load the newest usable snapshot and bring it up to date with `store`.
+    ///
+    /// Replays only the facts after the snapshot's watermark. A snapshot is
+    /// skipped if it is damaged or holds other types, as with
+    /// [`SnapshotStore::load_latest`], or if the store was rewritten so that
+    /// its watermark no longer matches; older ones are tried in turn, and
+    /// if none is usable the state is rebuilt from the first fact.
+    ///
+    /// The result is not saved; pass it to [`SnapshotStore::save`] for that.
+    pub fn restore<E, V, S, C, A>(
+        &self,
+        store: &FactStore<E, V, S, C>,
+    ) -> Result<Snapshot<E, A>, SnapshotError>
+    where
+        E: Serialize + DeserializeOwned + Clone + Eq + Hash,
+        V: Serialize + DeserializeOwned + Clone,
+        S: Serialize + DeserializeOwned + Clone,
+        C: Codec,
+        A: FactAggregator<E, V, S> + Default + DeserializeOwned,
+    {
+        for (_, path) in self.files()?.iter().rev() {
+            let Some(mut snapshot) = Self::load_usable(path)? else {
+                continue;
+            };
+            match replay(&mut snapshot.state, &mut snapshot.watermark, store) {
+                Ok(_) => return Ok(snapshot),
+                // Taken of a store that was rewritten since; try an older one
+                Err(StoreError::CursorMismatch { .. }) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut snapshot = Snapshot::default();
+        replay(&mut snapshot.state, &mut snapshot.watermark, store)?;
+        Ok(snapshot)
+    }
+
+    /// Check the header and checksum of every snapshot.
+    ///
+    /// Returns a [`SnapshotError::Damaged`] for each snapshot that fails,
+    /// oldest first. Whether a snapshot's state deserializes into particular
+    /// types is not checked.
+    pub fn verify(&self) -> Result<Vec<SnapshotError>, SnapshotError> {
+        let mut damaged = Vec::new();
+        for (_, path) in self.files()? {
+            match read_state(&path) {
+                Ok(_) => {}
+                Err(e @ SnapshotError::Damaged { .. }) => damaged.push(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(damaged)
+    }
+
+    /// Read and check the snapshot at `path`.
+    fn load<E, A>(path: &Path) -> Result<Snapshot<E, A>, SnapshotError>
+    where
+        E: DeserializeOwned + Eq + Hash,
+        A: DeserializeOwned,
+    {
+        let state: State<E, A> = serde_json::from_slice(&read_state(path)?)?;
+        Ok(Snapshot {
+            state: state.entries.into_iter().collect(),
+            watermark: state.watermark,
+        })
+    }
+
+    /// Read the snapshot at `path`, or `None` if it is damaged or does not
+    /// hold the requested types.
+    fn load_usable<E, A>(path: &Path) -> Result<Option<Snapshot<E, A>>, SnapshotError>
+    where
+        E: DeserializeOwned + Eq + Hash,
+        A: DeserializeOwned,
+    {
+        match Self::load(path) {
+            Ok(snapshot) => Ok(Some(snapshot)),
+            Err(SnapshotError::Damaged { .. } | SnapshotError::Serialization(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Snapshot files with their numbers, oldest first.
+    fn files(&self) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if let Some(number) = number(&path) {
+                files.push((number, path));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Delete the snapshots past the retention, and any left half written.
+    fn prune(&self) -> io::Result<()> {
+        let files = self.files()?;
+        let old = files.len().saturating_sub(self.retention);
+        for (_, path) in &files[..old] {
+            remove(path)?;
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let temporary = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(PREFIX) && name.ends_with(".tmp"));
+            if temporary {
+                remove(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state stored in the snapshot file at `path`, once its header and
+/// checksum have been checked.
+fn read_state(path: &Path) -> Result<Vec<u8>, SnapshotError> {
+    let damaged = |reason: String| SnapshotError::Damaged {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let mut bytes = fs::read(path)?;
+    let Some(newline) = bytes.iter().position(|&b| b == b'\n') else {
+        return Err(damaged("no header".into()));
+    };
+    let header: Header = serde_json::from_slice(&bytes[..newline])
+        .map_err(|e| damaged(format!("unreadable header: {e}")))?;
+    if header.format != FORMAT {
+        return Err(damaged(format!("unknown format {}", header.format)));
+    }
+
+    let state = bytes.split_off(newline + 1);
+    if state.len() as u64 != header.len {
+        return Err(damaged(format!(
+            "{} bytes of state where the header says {}",
+            state.len(),
+            header.len
+        )));
+    }
+    if crc32c::crc32c(&state) != header.checksum {
+        return Err(damaged("checksum mismatch".into()));
+    }
+    Ok(state)
+}
+
+/// Number of the snapshot file at `path`, if it is one.
+fn number(path: &Path) -> Option<u64> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(PREFIX)?
+        .strip_suffix(&format!(".{EXTENSION}"))?
+        .parse()
+        .ok()
+}
+
+/// Delete the file at `path` if it is still there.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate_facts;
+    use crate::testing::create_entity_facts;
+    use tempfile::{NamedTempFile, TempDir};
+
+    /// Counts the facts asserted about each entity.
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    struct Count(u32);
+
+    impl FactAggregator<String, u32, String> for Count {
+        fn assert(&mut self, _value: &u32, _source: &String) {
+            self.0 += 1;
+        }
+
+        fn retract(&mut self, _value: &u32, _source: &String) {
+            self.0 -= 1;
+        }
+    }
+
+    #[test]
+    fn restore_replays_only_facts_after_the_watermark() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let facts = create_entity_facts(10);
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&facts[..6]).unwrap();
+
+        let snapshots = SnapshotStore::open(dir.path()).unwrap();
+        let snapshot: Snapshot<String, Count> = snapshots.restore(&store).unwrap();
+        assert_eq!(snapshot.timestamp(), Some(*facts[5].timestamp()));
+        assert_eq!(snapshot.state()["entity0"], Count(2));
+
+        // Mark the saved state, to tell it apart from a rebuilt one
+        let watermark = snapshot.watermark();
+        let mut state = snapshot.into_state();
+        state.insert("marker".to_string(), Count(100));
+        snapshots.save(&Snapshot::new(state, watermark)).unwrap();
+
+        store.append_batch(&facts[6..]).unwrap();
+        let restored: Snapshot<String, Count> = snapshots.restore(&store).unwrap();
+        assert_eq!(restored.state()["marker"], Count(100));
+        let mut expected: HashMap<String, Count> = aggregate_facts(facts.clone());
+        expected.insert("marker".to_string(), Count(100));
+        assert_eq!(restored.state(), &expected);
+        assert_eq!(restored.timestamp(), Some(*facts[9].timestamp()));
+    }
+
+    #[test]
+    fn damaged_snapshots_are_skipped_and_reported() {
+        let dir = TempDir::new().unwrap();
+        let snapshots = SnapshotStore::open(dir.path()).unwrap();
+        let older = Snapshot::new(HashMap::from([("a".to_string(), Count(1))]), None);
+        let newer = Snapshot::new(HashMap::from([("a".to_string(), Count(2))]), None);
+        snapshots.save(&older).unwrap();
+        let newest = snapshots.save(&newer).unwrap();
+        assert_eq!(
+            snapshots.load_latest::<String, Count>().unwrap(),
+            Some(newer)
+        );
+        assert!(snapshots.verify().unwrap().is_empty());
+
+        // Flip a byte of the state
+        let mut bytes = fs::read(&newest).unwrap();
+        let last = bytes.len() - 3;
+        bytes[last] ^= 0x01;
+        fs::write(&newest, bytes).unwrap();
+
+        let damaged = snapshots.verify().unwrap();
+        assert!(matches!(
+            &damaged[..],
+            [SnapshotError::Damaged { path, .. }] if path == &newest
+        ));
+        assert_eq!(
+            snapshots.load_latest::<String, Count>().unwrap(),
+            Some(older)
+        );
+        // State of another type does not load
+        assert_eq!(snapshots.load_latest::<String, String>().unwrap(), None);
+    }
+
+    #[test]
+    fn unreadable_snapshots_are_an_error() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let store: FactStore<String, u32, String> =
+            FactStore::open_or_create(temp_file.path()).unwrap();
+        let snapshots = SnapshotStore::open(dir.path()).unwrap();
+        snapshots
+            .save(&Snapshot::<String, Count>::new(HashMap::new(), None))
+            .unwrap();
+
+        // Reading a directory fails with an I/O error, not as damage
+        fs::create_dir(dir.path().join("snapshot-00000000000000000009.json")).unwrap();
+
+        assert!(matches!(
+            snapshots.load_latest::<String, Count>(),
+            Err(SnapshotError::Io(_))
+        ));
+        assert!(matches!(
+            snapshots.restore::<_, _, _, _, Count>(&store),
+            Err(SnapshotError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_snapshots() {
+        let dir = TempDir::new().unwrap();
+        let snapshots = SnapshotStore::open(dir.path()).unwrap().with_retention(2);
+        fs::write(
+            dir.path().join("snapshot-00000000000000000009.json.tmp"),
+            b"{",
+        )
+        .unwrap();
+
+        let saved: Vec<_> = (0..4)
+            .map(|i| {
+                let state = HashMap::from([("a".to_string(), Count(i))]);
+                snapshots.save(&Snapshot::new(state, None)).unwrap()
+            })
+            .collect();
+
+        let mut left: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        left.sort();
+        assert_eq!(left, saved[2..]);
+        let latest = snapshots.load_latest::<String, Count>().unwrap().unwrap();
+        assert_eq!(latest.state()["a"], Count(3));
+    }
+
+    #[test]
+    fn restore_rebuilds_when_the_store_was_rewritten() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let facts = create_entity_facts(6);
+        let snapshots = SnapshotStore::open(dir.path()).unwrap();
+        {
+            let store = FactStore::open_or_create(temp_file.path()).unwrap();
+            store.append_batch(&facts[..3]).unwrap();
+            let snapshot: Snapshot<String, Count> = snapshots.restore(&store).unwrap();
+            snapshots.save(&snapshot).unwrap();
+        }
+
+        // Replace the store with different facts
+        fs::write(temp_file.path(), b"").unwrap();
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&facts[3..]).unwrap();
+
+        let restored: Snapshot<String, Count> = snapshots.restore(&store).unwrap();
+        assert_eq!(restored.state(), &aggregate_facts(facts[3..].to_vec()));
+    }
+}
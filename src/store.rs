@@ -4,15 +4,30 @@
 // Add to: src/store.rs (new file)
 
 use crate::{
-    io::{FactStreamWriter, ReadError, WriteError},
+    cursor::{Cursor, LinePosition},
+    follow::FollowIterator,
+    io::{
+        common::{self, LineFormat},
+        compression::{self, StreamFile},
+        index::TimestampIndex,
+        recover, seek,
+        segment::{self, SegmentLog},
+        verify, Codec, DurabilityPolicy, FactStreamWriter, JsonCodec, ReadError, RecoveryPolicy,
+        RecoveryReport, SegmentPolicy, SyncSchedule, VerifyReport, WriteError,
+    },
+    options::{FactStoreOptions, OpenMode, OrderingPolicy},
     Fact,
 };
 use chrono::{DateTime, Utc};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, MutexGuard, RwLock};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
-    io::BufRead,
+    collections::VecDeque,
+    io::{BufRead, Read, Seek, SeekFrom},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime},
 };
 use thiserror::Error;
 
@@ -32,6 +47,25 @@ pub enum StoreError {
         new: DateTime<Utc>,
         latest: DateTime<Utc>,
     },
+
+    #[error("Failed to read fact at line {line} (byte offset {offset}): {source}")]
+    ReadFailed {
+        line: u64,
+        offset: u64,
+        source: ReadError,
+    },
+
+    #[error("The store changed under the cursor: its fact is no longer at byte offset {offset}")]
+    CursorMismatch { offset: u64 },
+
+    #[error("The store was opened read-only")]
+    ReadOnly,
+
+    /// The write of a whole group of batches failed under group commit
+    /// (`AsyncFactStore::with_group_commit`); every batch in the group
+    /// shares this one error.
+    #[error("Group commit failed: {0}")]
+    GroupCommit(Arc<StoreError>),
 }
 
 /// A thread-safe fact store that maintains timestamp ordering.
@@ -77,13 +111,111 @@ pub enum StoreError {
 /// # Ok(())
 /// # }
 /// ```
-pub struct FactStore<E, V, S> {
+///
+/// Facts are stored as newline-delimited JSON unless the store is opened
+/// with another [`Codec`] through [`FactStore::open_with_codec`].
+///
+/// # Sequence numbers
+///
+/// Every fact has a sequence number: its position in the store, counting
+/// from 0 in the order the facts were appended. Unlike timestamps they never
+/// repeat, so they make exact positions for replication and checkpoints.
+/// [`FactStore::append_batch`] returns the numbers it assigned,
+/// [`FactStore::iter_from_seq`] starts iterating at one, and the iterators
+/// report the number of every fact they yield. Sequence numbers are not
+/// written to disk but derived from the facts before, so they stay valid as
+/// long as the store is only ever appended to.
+pub struct FactStore<E, V, S, C = JsonCodec> {
+    /// The stream file, or the directory of a segmented store
     path: PathBuf,
     /// Latest timestamp, cached for quick access
     latest_timestamp: RwLock<Option<DateTime<Utc>>>,
+    /// The file being appended to, as it was when the latest timestamp was
+    /// last read from it or written to it
+    seen: Mutex<FileState>,
+    /// Held for the whole of each append, and remembers the sequence number
+    /// the last one left off at
+    appends: Mutex<Option<SequenceMark>>,
+    /// Batches appended since the last fsync, under the durability policy
+    schedule: Mutex<SyncSchedule>,
+    /// Sparse timestamp index, if enabled
+    index: Option<Mutex<TimestampIndex>>,
+    /// Segment files and manifest, if this is a segmented store
+    segments: Option<Mutex<SegmentLog<C>>>,
+    /// How appended batches are laid out on disk
+    format: LineFormat,
+    /// Opened with [`OpenMode::ReadOnly`]
+    read_only: bool,
+    ordering: OrderingPolicy,
+    /// How long appends wait for another process's write lock
+    lock_timeout: Duration,
+    retry_interval: Duration,
+    /// Encoding of the records on disk
+    codec: C,
     _phantom: std::marker::PhantomData<(E, V, S)>,
 }
 
+/// Length and modification time of a stream file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileState {
+    path: PathBuf,
+    len: u64,
+    /// `None` if not known, in which case only the length is compared
+    modified: Option<SystemTime>,
+}
+
+impl FileState {
+    /// The state of the file at `path` now; empty if it does not exist.
+    fn of(path: &Path) -> std::io::Result<Self> {
+        let (len, modified) = match std::fs::metadata(path) {
+            Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, None),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            len,
+            modified,
+        })
+    }
+
+    /// Whether the file may have been written to since `self`, given its
+    /// state `now`.
+    fn changed(&self, now: &FileState) -> bool {
+        self.path != now.path
+            || self.len != now.len
+            || (self.modified.is_some() && self.modified != now.modified)
+    }
+}
+
+/// A line start in the file being appended to, and the sequence number of
+/// the first fact at or after it.
+#[derive(Clone)]
+pub(crate) struct SequenceMark {
+    pub(crate) path: PathBuf,
+    pub(crate) offset: u64,
+    pub(crate) seq: u64,
+}
+
+#[cfg(feature = "tokio")]
+impl SequenceMark {
+    /// Sequence number of the first fact at or after byte `offset` of the
+    /// single stream file `path`, counting from `mark` if it is in that file
+    /// and not past `offset`, and from the start of the file otherwise.
+    pub(crate) fn seq_at<C: Codec>(
+        mark: Option<&Self>,
+        codec: &C,
+        path: &Path,
+        offset: u64,
+    ) -> std::io::Result<u64> {
+        let (from, seq) = match mark {
+            Some(mark) if mark.path == path && mark.offset <= offset => (mark.offset, mark.seq),
+            _ => (0, 0),
+        };
+        Ok(seq + common::count_facts(codec, path, from, offset)?)
+    }
+}
+
 impl<E, V, S> FactStore<E, V, S>
 where
     E: Serialize + DeserializeOwned + Clone,
@@ -91,104 +223,754 @@ where
     S: Serialize + DeserializeOwned + Clone,
 {
     /// Open an existing fact store or create a new one.
+    ///
+    /// An incomplete record left at the end of the file by an interrupted
+    /// write is moved to a `<path>.corrupt` sidecar (see
+    /// [`RecoveryPolicy::Quarantine`]). Use [`FactStore::open_with_recovery`]
+    /// to choose the policy and learn what was done. The repair needs the
+    /// exclusive write lock: if the file ends part way through a record
+    /// while another process holds the lock, opening fails with
+    /// [`WriteError::AlreadyLocked`] unless a lock timeout is set with
+    /// [`FactStoreOptions::with_lock_timeout`].
     pub fn open_or_create(path: impl Into<PathBuf>) -> Result<Self, StoreError> {
-        let path = path.into();
+        Self::open_with(FactStoreOptions::new(path))
+    }
+
+    /// Open or create a fact store that maintains a sparse timestamp index.
+    ///
+    /// The byte offset of every `interval`-th fact is recorded in a sidecar
+    /// file (`<path>.idx`) so `iter_from` can seek close to its starting point
+    /// instead of scanning from the start. A missing or stale index is rebuilt.
+    pub fn open_or_create_with_index(
+        path: impl Into<PathBuf>,
+        interval: usize,
+    ) -> Result<Self, StoreError> {
+        Self::open(FactStoreOptions::new(path), Some(interval), None).map(|(store, _)| store)
+    }
+
+    /// Open or create a fact store, repairing an interrupted write with `policy`.
+    ///
+    /// The end of the file is checked for an incomplete record under the
+    /// exclusive write lock. The returned report says whether anything was
+    /// truncated or quarantined.
+    ///
+    /// Like an append, the repair fails with [`WriteError::AlreadyLocked`]
+    /// if another process holds the lock; open with
+    /// [`FactStoreOptions::with_lock_timeout`] to wait for it instead.
+    pub fn open_with_recovery(
+        path: impl Into<PathBuf>,
+        policy: RecoveryPolicy,
+    ) -> Result<(Self, RecoveryReport), StoreError> {
+        Self::open(
+            FactStoreOptions::new(path).with_recovery(policy),
+            None,
+            None,
+        )
+    }
+
+    /// Open or create a segmented fact store in the directory `dir`.
+    ///
+    /// Facts are written to a series of segment files, and a new segment is
+    /// started whenever the active one reaches the limits of `policy`. A
+    /// `manifest.json` in the directory records the first and last timestamp
+    /// of every sealed segment, so `iter_from` skips whole segments that end
+    /// before its starting point. Old segments can be backed up or removed
+    /// as plain files.
+    ///
+    /// Stores in several processes can append to the same directory. An
+    /// append holds an exclusive lock on `manifest.lock` in the directory
+    /// from choosing the segment to write to until the batch is written, and
+    /// re-reads the manifest first, so segments rolled by others are seen.
+    pub fn open_segmented(
+        dir: impl Into<PathBuf>,
+        policy: SegmentPolicy,
+    ) -> Result<Self, StoreError> {
+        Self::open(FactStoreOptions::new(dir), None, Some(policy)).map(|(store, _)| store)
+    }
+}
+
+impl<E, V, S, C> FactStore<E, V, S, C>
+where
+    E: Serialize + DeserializeOwned + Clone,
+    V: Serialize + DeserializeOwned + Clone,
+    S: Serialize + DeserializeOwned + Clone,
+    C: Codec,
+{
+    /// Open an existing fact store or create a new one, encoded with `codec`.
+    ///
+    /// Behaves like [`FactStore::open_or_create`]. The codec is not recorded
+    /// in the file, so a store must always be opened with the codec it was
+    /// written with.
+    pub fn open_with_codec(path: impl Into<PathBuf>, codec: C) -> Result<Self, StoreError> {
+        Self::open_with(FactStoreOptions::new(path).with_codec(codec))
+    }
+
+    /// Open a fact store as described by `options`.
+    ///
+    /// Every other way of opening a store is a shorthand for some options;
+    /// this one can also refuse to create the store, open it read-only, wait
+    /// for other writers' locks, and set the durability, ordering and
+    /// recovery policies. See [`FactStoreOptions`].
+    pub fn open_with(options: FactStoreOptions<C>) -> Result<Self, StoreError> {
+        Self::open(options, None, None).map(|(store, _)| store)
+    }
+
+    fn open(
+        options: FactStoreOptions<C>,
+        index_interval: Option<usize>,
+        segment_policy: Option<SegmentPolicy>,
+    ) -> Result<(Self, RecoveryReport), StoreError> {
+        options.check_exists()?;
+        let read_only = options.mode == OpenMode::ReadOnly;
+        let FactStoreOptions {
+            path,
+            lock_timeout,
+            retry_interval,
+            durability,
+            ordering,
+            recovery,
+            codec,
+            ..
+        } = options;
 
         // Create parent directory if needed
-        if let Some(parent) = path.parent() {
+        if let Some(parent) = path.parent().filter(|_| !read_only) {
             std::fs::create_dir_all(parent)?;
         }
 
+        let segments = segment_policy
+            .map(|policy| {
+                SegmentLog::open(&path, policy, codec.clone())
+                    .map(|log| log.with_lock_wait(lock_timeout, retry_interval))
+            })
+            .transpose()?;
+
+        // Only the file being appended to can end in an interrupted write
+        let active = segments
+            .as_ref()
+            .map_or_else(|| path.clone(), SegmentLog::active_path);
+
+        // Repair before reading anything, so a torn record is never seen.
+        // Readers stop before a torn record anyway, so a read-only store
+        // can leave it
+        let report = if read_only {
+            RecoveryReport {
+                valid_len: file_len(&active)?,
+                ..RecoveryReport::default()
+            }
+        } else {
+            recover(&codec, &active, recovery, lock_timeout, retry_interval)?
+        };
+
         // Read latest timestamp if file exists
-        let latest_timestamp = if path.exists() {
-            Self::read_latest_timestamp(&path)?
+        let latest_timestamp = if active.exists() {
+            Self::read_latest_timestamp(&codec, &mut std::fs::File::open(&active)?)?
         } else {
             None
         };
-
-        Ok(Self {
+        let latest_timestamp =
+            latest_timestamp.or_else(|| segments.as_ref().and_then(SegmentLog::sealed_latest));
+        let seen = FileState::of(&active)?;
+
+        // Segments are already skipped by timestamp, so they are not indexed
+        let index = index_interval
+            .filter(|_| segments.is_none())
+            .map(|interval| TimestampIndex::open(&path, interval, &codec))
+            .transpose()?
+            .map(Mutex::new);
+
+        let store = Self {
             path,
             latest_timestamp: RwLock::new(latest_timestamp),
+            seen: Mutex::new(seen),
+            appends: Mutex::new(None),
+            schedule: Mutex::new(SyncSchedule::new(durability)),
+            index,
+            segments: segments.map(Mutex::new),
+            format: LineFormat::default(),
+            read_only,
+            ordering,
+            lock_timeout,
+            retry_interval,
+            codec,
             _phantom: std::marker::PhantomData,
-        })
+        };
+
+        Ok((store, report))
+    }
+
+    /// Write every appended batch as a single framed record.
+    ///
+    /// Without framing, each fact is its own line, and a crash part way
+    /// through `append_batch` can leave the first facts of a batch on disk.
+    /// With framing, a batch becomes visible to readers only once it is
+    /// completely written, and recovery on open drops a batch that never
+    /// was. Stores can switch at any time; both forms are always readable.
+    pub fn with_batch_framing(mut self, enabled: bool) -> Self {
+        self.format.framed = enabled;
+        self
+    }
+
+    /// Append a CRC32C checksum to every line written by this store.
+    ///
+    /// Iterators then report a line whose contents no longer match its
+    /// checksum as [`ReadError::ChecksumMismatch`], and
+    /// [`FactStore::verify`] lists every such line. Lines written without a
+    /// checksum are still read as before.
+    pub fn with_checksums(mut self, enabled: bool) -> Self {
+        self.format.checksums = enabled;
+        self
+    }
+
+    /// Fsync appended batches according to `policy` instead of after each one.
+    ///
+    /// By default every append is synced to disk before it returns
+    /// ([`DurabilityPolicy::Always`]). The other policies trade the last few
+    /// batches in case of an operating system crash or power loss for much
+    /// faster appends; see [`DurabilityPolicy`] for exactly what each can
+    /// lose. A crash of the process alone never loses an acknowledged append.
+    /// Call [`FactStore::flush_durable`] to sync at a point of your choosing.
+    pub fn with_durability(mut self, policy: DurabilityPolicy) -> Self {
+        self.schedule = Mutex::new(SyncSchedule::new(policy));
+        self
+    }
+
+    /// Force every fact appended so far to stable storage.
+    ///
+    /// Waits for appends in progress. Under any durability policy but
+    /// `Always`, call this before relying on the last appends surviving an
+    /// operating system crash, for example before shutting down.
+    pub fn flush_durable(&self) -> Result<(), StoreError> {
+        if self.read_only {
+            return Ok(());
+        }
+        let _appends = self.appends.lock();
+        // Sealed segments were synced when they were rolled
+        let target = match &self.segments {
+            Some(log) => log.lock().active_path(),
+            None => self.path.clone(),
+        };
+        sync_file(&target)?;
+        self.schedule.lock().synced();
+        Ok(())
+    }
+
+    /// Compress the sealed segments still waiting to be, now.
+    ///
+    /// Appends compress the segments they seal once they have released the
+    /// store's locks, and retry segments that failed before, but cannot
+    /// report a failure without failing an append whose facts were written.
+    /// This reports it. Segments that fail stay queued for the next try. Does
+    /// nothing unless the store is segmented with
+    /// [`SegmentPolicy::with_compression`].
+    #[cfg(feature = "compression")]
+    pub fn compress_sealed(&self) -> Result<(), StoreError> {
+        match &self.segments {
+            Some(log) if !self.read_only => Ok(segment::compress_sealed(log)?),
+            _ => Ok(()),
+        }
     }
 
     /// Append a single fact, enforcing timestamp ordering.
     pub fn append(&self, fact: Fact<E, V, S>) -> Result<(), StoreError> {
-        self.append_batch(&[fact])
+        self.append_batch(&[fact]).map(|_| ())
     }
 
     /// Append a batch of facts, enforcing timestamp ordering.
     ///
-    /// All facts are written atomically. If any fact violates timestamp ordering,
-    /// the entire batch is rejected.
-    pub fn append_batch(&self, facts: &[Fact<E, V, S>]) -> Result<(), StoreError> {
+    /// If any fact violates timestamp ordering, the entire batch is rejected.
+    /// Ordering is checked against the last fact in the file while the
+    /// exclusive write lock is held, so facts appended by other processes
+    /// count too, and the cached latest timestamp is refreshed from it.
+    /// Concurrent appends through the same store wait their turn and are
+    /// checked and written one at a time, in the order they were called.
+    /// The batch is written with a single write call; see
+    /// [`FactStore::with_batch_framing`] for batches that stay atomic across
+    /// a crash, and [`FactStore::with_durability`] for when it is synced to
+    /// disk.
+    ///
+    /// Returns the range of sequence numbers assigned to the facts, in
+    /// order. Facts appended by other processes in the meantime are taken
+    /// into account. The first append through a store counts the facts
+    /// already in it, which reads the whole file unless the store is indexed.
+    pub fn append_batch(&self, facts: &[Fact<E, V, S>]) -> Result<Range<u64>, StoreError> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly);
+        }
         if facts.is_empty() {
-            return Ok(());
+            let next = self.next_seq()?;
+            return Ok(next..next);
         }
 
-        // Check timestamp ordering
-        let latest = *self.latest_timestamp.read();
-
-        for fact in facts {
-            if let Some(latest_ts) = latest {
-                if fact.timestamp() < &latest_ts {
-                    return Err(StoreError::TimestampOrdering {
-                        new: *fact.timestamp(),
-                        latest: latest_ts,
-                    });
-                }
+        // Appends queue up here and are checked and written one at a time,
+        // in the order they arrived
+        let mut mark = self.appends.lock();
+        let result = self.append_locked(&mut mark, facts);
+        MutexGuard::unlock_fair(mark);
+
+        // The index is only an accelerator and is validated on open, so a
+        // failure here must not turn a successful write into an error
+        if result.is_ok() {
+            if let Some(index) = &self.index {
+                let _ = index.lock().catch_up(&self.path, &self.codec);
             }
         }
 
-        // Write facts (FactStreamWriter handles locking)
-        let mut writer = FactStreamWriter::open(&self.path)?;
-        writer.write_batch(facts)?;
-
-        // Update cached latest timestamp
-        if let Some(last_fact) = facts.last() {
-            let mut latest = self.latest_timestamp.write();
-            *latest = Some(*last_fact.timestamp());
+        // Segments sealed by this append are compressed only now that the
+        // locks are released. A segment that fails to compress is still
+        // complete and readable as it is, and stays queued;
+        // `compress_sealed` reports why
+        #[cfg(feature = "compression")]
+        if result.is_ok() {
+            self.compress_sealed().ok();
         }
 
-        Ok(())
+        result
+    }
+
+    /// Check and write a non-empty batch while holding the append lock.
+    fn append_locked(
+        &self,
+        mark: &mut Option<SequenceMark>,
+        facts: &[Fact<E, V, S>],
+    ) -> Result<Range<u64>, StoreError> {
+        // Reject what is already known to be out of order without touching
+        // the file; the cache can only lag behind the file, never lead it
+        self.ordering.check(facts, *self.latest_timestamp.read())?;
+
+        // Hold the segment log, and the manifest lock that keeps other
+        // processes from rolling it, until the batch is written
+        let mut segments = self.segments.as_ref().map(|log| log.lock());
+        let (target, manifest_lock) = match segments.as_deref_mut() {
+            Some(log) => {
+                let first = *facts[0].timestamp();
+                let last = *facts[facts.len() - 1].timestamp();
+                let (target, lock) = log.prepare_append(first, last)?;
+                (target, Some(lock))
+            }
+            None => (self.path.clone(), None),
+        };
+        let len = file_len(&target)?;
+        let first = self.seq_at(mark.as_ref(), segments.as_deref_mut(), &target, len)?;
+        // A segment rolled to just now has no facts of its own yet
+        let sealed_latest = segments.as_deref().and_then(SegmentLog::sealed_latest);
+
+        // Write facts (FactStreamWriter handles locking). Other processes
+        // may have appended since the cache was last updated, so ordering is
+        // checked again against the last fact in the file, under the lock
+        let mut schedule = self.schedule.lock();
+        let mut writer = FactStreamWriter::open(&target)?
+            .with_format(self.format)
+            .with_codec(self.codec.clone())
+            .with_lock_wait(self.lock_timeout, self.retry_interval)
+            .with_schedule(*schedule);
+        let written = writer.append_batch(facts, |file| {
+            let latest = Self::read_latest_timestamp(&self.codec, file)?.or(sealed_latest);
+            *self.latest_timestamp.write() = latest;
+            self.ordering.check(facts, latest)
+        })?;
+        *schedule = writer.schedule();
+        drop(schedule);
+        drop(manifest_lock);
+        drop(segments);
+
+        // Another process may have appended since the file was measured
+        let first = first + common::count_facts(&self.codec, &target, len, written.start)?;
+        let next = first + facts.len() as u64;
+
+        // Update cached latest timestamp. The modification time is left
+        // unknown: another process may have written since
+        let mut seen = self.seen.lock();
+        *self.latest_timestamp.write() = Some(*facts[facts.len() - 1].timestamp());
+        *seen = FileState {
+            path: target.clone(),
+            len: written.end,
+            modified: None,
+        };
+        drop(seen);
+
+        *mark = Some(SequenceMark {
+            path: target,
+            offset: written.end,
+            seq: next,
+        });
+
+        Ok(first..next)
+    }
+
+    /// The sequence number the next appended fact will get.
+    ///
+    /// This is the number of facts in the store. Like the first append, the
+    /// first call counts them.
+    pub fn next_seq(&self) -> Result<u64, StoreError> {
+        let mark = self.appends.lock();
+        let mut segments = self.segments.as_ref().map(|log| log.lock());
+        let target = match segments.as_deref_mut() {
+            Some(log) => {
+                // Another process may have rolled to a newer segment
+                log.reload()?;
+                log.active_path()
+            }
+            None => self.path.clone(),
+        };
+        let len = file_len(&target)?;
+        self.seq_at(mark.as_ref(), segments.as_deref_mut(), &target, len)
+    }
+
+    /// Sequence number of the first fact at or after byte `offset` of the
+    /// file being appended to.
+    ///
+    /// Counts from `mark` if it is in the same file and not past `offset`,
+    /// and otherwise from the nearest point the index or the segment log
+    /// knows the sequence number of.
+    fn seq_at(
+        &self,
+        mark: Option<&SequenceMark>,
+        segments: Option<&mut SegmentLog<C>>,
+        path: &Path,
+        offset: u64,
+    ) -> Result<u64, StoreError> {
+        let (from, seq) = match mark {
+            Some(mark) if mark.path == path && mark.offset <= offset => (mark.offset, mark.seq),
+            _ => match (segments, &self.index) {
+                (Some(log), _) => (0, log.first_seq(path)?),
+                (None, Some(index)) => index.lock().anchor(offset),
+                (None, None) => (0, 0),
+            },
+        };
+        Ok(seq + common::count_facts(&self.codec, path, from, offset)?)
     }
 
     /// Get the latest timestamp in the store.
+    ///
+    /// Includes facts appended by other processes, or by a
+    /// [`FactStreamWriter`] on the same file: the file is checked for
+    /// changes, which costs one metadata lookup, and its last line is read
+    /// again if it changed. Should that fail, the timestamp read last is
+    /// returned; [`FactStore::refresh`] reports the error.
     pub fn latest_timestamp(&self) -> Option<DateTime<Utc>> {
+        // The cached value is the best there is if the file cannot be read
+        let _ = self.refresh();
         *self.latest_timestamp.read()
     }
 
+    /// Pick up the latest timestamp from the file if it changed.
+    ///
+    /// Compares the length and modification time of the file being
+    /// appended to with what they were when its last line was read, or
+    /// when this store last appended to it, and reads the last line again
+    /// if they differ. In a segmented store, segments started by another
+    /// process since are found too. [`FactStore::latest_timestamp`] calls
+    /// this itself.
+    pub fn refresh(&self) -> Result<(), StoreError> {
+        // Taken before `seen`, which appends lock while holding the log
+        let (path, sealed_latest) = match &self.segments {
+            Some(log) => {
+                let log = log.lock();
+                let mut path = log.active_path();
+                while let Some(next) = segment::next_segment(&path) {
+                    path = next;
+                }
+                (path, log.sealed_latest())
+            }
+            None => (self.path.clone(), None),
+        };
+
+        let mut seen = self.seen.lock();
+        let now = FileState::of(&path)?;
+        if seen.changed(&now) {
+            let latest = match std::fs::File::open(&path) {
+                Ok(mut file) => Self::read_latest_timestamp(&self.codec, &mut file)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            };
+            *self.latest_timestamp.write() = latest.or(sealed_latest);
+        }
+        *seen = now;
+        Ok(())
+    }
+
     /// Iterate over all facts in the store.
-    pub fn iter(&self) -> FactIterator<E, V, S> {
+    pub fn iter(&self) -> FactIterator<E, V, S, C> {
         self.iter_from(DateTime::<Utc>::MIN_UTC)
     }
 
     /// Iterate over facts starting from a specific timestamp.
     ///
+    /// Iteration ends at the first line that cannot be read or parsed; use
+    /// [`FactStore::try_iter_from`] to tell that apart from the end of the
+    /// store.
+    ///
     /// This efficiently seeks to the first fact at or after the given timestamp.
     ///
     /// # Performance
     ///
-    /// Currently performs a linear scan from the start. For large fact streams,
-    /// consider adding an index file for faster seeking.
-    pub fn iter_from(&self, since: DateTime<Utc>) -> FactIterator<E, V, S> {
-        FactIterator::new(self.path.clone(), since)
+    /// Since facts are stored in timestamp order, the starting point is found
+    /// by binary search over byte offsets, decoding only the timestamp of each
+    /// probed line. Stores opened with [`FactStore::open_or_create_with_index`]
+    /// first narrow the search to the range between two indexed facts.
+    ///
+    /// If the search detects facts out of timestamp order (possible in files
+    /// written without `FactStore`), it falls back to a linear scan.
+    pub fn iter_from(&self, since: DateTime<Utc>) -> FactIterator<E, V, S, C> {
+        FactIterator {
+            inner: self.try_iter_from(since),
+        }
+    }
+
+    /// Iterate over all facts in the store, reporting read failures.
+    pub fn try_iter(&self) -> TryFactIterator<E, V, S, C> {
+        self.try_iter_from(DateTime::<Utc>::MIN_UTC)
+    }
+
+    /// Iterate over facts starting from a specific timestamp, reporting read failures.
+    ///
+    /// Seeks like [`FactStore::iter_from`], but yields an error with the line
+    /// number and byte offset of any line that cannot be read or parsed
+    /// instead of silently ending the iteration.
+    pub fn try_iter_from(&self, since: DateTime<Utc>) -> TryFactIterator<E, V, S, C> {
+        let (low, high) = self
+            .index
+            .as_ref()
+            .map_or((0, None), |index| index.lock().seek_range(since));
+        let files = self.files_from(since);
+        let anchor = self.anchor(files.first(), low);
+        TryFactIterator::new(
+            self.codec.clone(),
+            files,
+            self.segments.is_some(),
+            since,
+            (low, high),
+            anchor,
+        )
+    }
+
+    /// Iterate over facts starting from the one with sequence number `seq`.
+    ///
+    /// Yields nothing if the store holds no fact numbered `seq` yet. Like
+    /// [`FactStore::iter_from`], iteration ends at the first line that cannot
+    /// be read or parsed; use [`FactStore::try_iter_from_seq`] to tell that
+    /// apart from the end of the store.
+    ///
+    /// # Performance
+    ///
+    /// Sequence numbers are not stored, so the facts before `seq` are
+    /// counted. Stores opened with [`FactStore::open_or_create_with_index`]
+    /// start counting at the closest indexed fact, and segmented stores skip
+    /// whole segments using the fact counts in their manifest.
+    pub fn iter_from_seq(&self, seq: u64) -> FactIterator<E, V, S, C> {
+        FactIterator {
+            inner: self.try_iter_from_seq(seq),
+        }
+    }
+
+    /// Iterate over facts starting from the one with sequence number `seq`,
+    /// reporting read failures.
+    pub fn try_iter_from_seq(&self, seq: u64) -> TryFactIterator<E, V, S, C> {
+        let segmented = self.segments.is_some();
+        let start = match &self.segments {
+            Some(log) => log
+                .lock()
+                .files_from_seq(seq)
+                .map(|(files, base)| (files, 0, base)),
+            None => {
+                let (offset, base) = self
+                    .index
+                    .as_ref()
+                    .map_or((0, 0), |index| index.lock().seek_seq(seq));
+                Ok((vec![self.path.clone()], offset, base))
+            }
+        };
+
+        match start {
+            Ok((files, offset, base)) => {
+                let mut iter = TryFactIterator::open(
+                    self.codec.clone(),
+                    files,
+                    segmented,
+                    DateTime::<Utc>::MIN_UTC,
+                    offset,
+                    Some((offset, base)),
+                );
+                iter.skip = seq - base;
+                iter
+            }
+            Err(e) => TryFactIterator::failed(self.codec.clone(), e),
+        }
+    }
+
+    /// Iterate over the facts after the one `cursor` was taken on.
+    ///
+    /// Resumes exactly where an earlier iteration stopped: every fact after
+    /// the cursor's is yielded, including those with the same timestamp,
+    /// and none before it. Iteration ends at the first line that cannot be
+    /// read or parsed, or right away if the store was rewritten so that the
+    /// cursor's fact is no longer where it was; use
+    /// [`FactStore::try_iter_from_cursor`] to tell these apart.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use stainless_facts::{Cursor, FactStore};
+    /// # fn example(store: FactStore<String, String, String>, saved: Option<Cursor>) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut facts = match saved {
+    ///     Some(cursor) => store.try_iter_from_cursor(cursor),
+    ///     None => store.try_iter(),
+    /// };
+    /// while let Some(fact) = facts.next() {
+    ///     println!("{:?}", fact?);
+    /// }
+    /// let saved = facts.cursor(); // Persist this for the next run
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn iter_from_cursor(&self, cursor: Cursor) -> FactIterator<E, V, S, C> {
+        FactIterator {
+            inner: self.try_iter_from_cursor(cursor),
+        }
+    }
+
+    /// Iterate over the facts after the one `cursor` was taken on, reporting
+    /// read failures.
+    ///
+    /// Like [`FactStore::iter_from_cursor`], but yields
+    /// [`StoreError::CursorMismatch`] if the store was rewritten under the
+    /// cursor, and [`StoreError::ReadFailed`] for a line that cannot be read.
+    pub fn try_iter_from_cursor(&self, cursor: Cursor) -> TryFactIterator<E, V, S, C> {
+        let files = match &self.segments {
+            Some(log) => log
+                .lock()
+                .files_from(cursor.timestamp())
+                .into_iter()
+                .filter(|path| segment::sequence(path).is_some_and(|n| n >= cursor.segment()))
+                .collect(),
+            None => vec![self.path.clone()],
+        };
+        let anchor = self.anchor(files.first(), cursor.offset());
+        TryFactIterator::resuming(
+            self.codec.clone(),
+            files,
+            self.segments.is_some(),
+            cursor,
+            anchor,
+        )
+    }
+
+    /// Follow the store from a specific timestamp, waiting for new facts.
+    ///
+    /// Yields the facts [`FactStore::try_iter_from`] would, then blocks until
+    /// more are appended, by this or any other process, instead of ending.
+    /// A line that is still being written is picked up once it is complete,
+    /// and segmented stores are followed across segment rolls.
+    ///
+    /// The iterator can be given a timeout and cancelled through a
+    /// [`FollowHandle`](crate::FollowHandle). With the `notify` feature it is
+    /// woken by file change notifications; otherwise it polls.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use stainless_facts::FactStore;
+    /// # use chrono::{DateTime, Utc};
+    /// # use std::time::Duration;
+    /// # let store: FactStore<String, String, String> = FactStore::open_or_create("facts.stream").unwrap();
+    /// let mut follow = store
+    ///     .follow(DateTime::<Utc>::MIN_UTC)
+    ///     .with_timeout(Duration::from_secs(30));
+    /// let handle = follow.handle(); // call handle.cancel() to stop early
+    ///
+    /// for fact in follow.by_ref() {
+    ///     println!("{:?}", fact.unwrap());
+    /// }
+    /// ```
+    pub fn follow(&self, since: DateTime<Utc>) -> FollowIterator<E, V, S, C> {
+        let segmented = self.segments.is_some();
+        let dir = if segmented {
+            self.path.as_path()
+        } else {
+            match self.path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            }
+        };
+        FollowIterator::new(self.try_iter_from(since).following(), dir)
+    }
+
+    /// A line start at or before `offset` in `file`, and the sequence number
+    /// of the first fact at or after it, if it can be found cheaply.
+    fn anchor(&self, file: Option<&PathBuf>, offset: u64) -> Option<(u64, u64)> {
+        match (&self.segments, &self.index) {
+            (Some(log), _) => {
+                let first = log.lock().first_seq(file?).ok()?;
+                Some((0, first))
+            }
+            (None, Some(index)) => Some(index.lock().anchor(offset)),
+            (None, None) => Some((0, 0)),
+        }
+    }
+
+    /// Files that may hold facts at or after `since`, oldest first.
+    fn files_from(&self, since: DateTime<Utc>) -> Vec<PathBuf> {
+        match &self.segments {
+            Some(log) => log.lock().files_from(since),
+            None => vec![self.path.clone()],
+        }
+    }
+
+    /// Check the whole store for damage.
+    ///
+    /// Walks every line and reports checksum mismatches, lines that cannot
+    /// be parsed, facts out of timestamp order and an incomplete last line,
+    /// each with its line number and byte offset. Unlike iteration, this
+    /// does not stop at the first problem.
+    pub fn verify(&self) -> Result<VerifyReport, StoreError> {
+        Ok(verify::<C, E, V, S>(
+            &self.codec,
+            &self.files_from(DateTime::<Utc>::MIN_UTC),
+        )?)
     }
 
     /// Read the latest timestamp from the file without caching.
-    fn read_latest_timestamp(path: &Path) -> Result<Option<DateTime<Utc>>, StoreError> {
-        let file = std::fs::File::open(path)?;
+    ///
+    /// Reads backwards from the end to the last complete line, so the cost
+    /// does not grow with the size of the store. Falls back to a full scan
+    /// only if that line cannot be parsed.
+    pub(crate) fn read_latest_timestamp(
+        codec: &C,
+        file: &mut std::fs::File,
+    ) -> Result<Option<DateTime<Utc>>, StoreError> {
+        match common::read_last_line(file, C::DELIMITER)? {
+            None => Ok(None),
+            Some((_, line)) => match common::parse_timestamps(codec, &line) {
+                Some((_, last)) => Ok(Some(last)),
+                None => Self::scan_latest_timestamp(codec, file),
+            },
+        }
+    }
+
+    /// Find the latest timestamp by reading every fact in the file.
+    fn scan_latest_timestamp(
+        codec: &C,
+        file: &mut std::fs::File,
+    ) -> Result<Option<DateTime<Utc>>, StoreError> {
+        file.seek(SeekFrom::Start(0))?;
         let mut reader = std::io::BufReader::new(file);
 
         let mut last_timestamp = None;
-        let mut line = String::new();
+        let mut line = Vec::new();
+        let mut facts = VecDeque::<Fact<E, V, S>>::new();
 
         // Read through file, keeping track of last timestamp
-        // This is O(n) but only done once at startup
-        while reader.read_line(&mut line).map_err(ReadError::from)? > 0 {
-            if let Ok(fact) = serde_json::from_str::<Fact<E, V, S>>(&line) {
-                last_timestamp = Some(*fact.timestamp());
+        while reader
+            .read_until(C::DELIMITER, &mut line)
+            .map_err(ReadError::from)?
+            > 0
+        {
+            if common::decode_line(codec, &line, &mut facts).is_ok() {
+                if let Some(fact) = facts.back() {
+                    last_timestamp = Some(*fact.timestamp());
+                }
+                facts.clear();
             }
             line.clear();
         }
@@ -200,39 +982,60 @@ where
 /// Iterator over facts in a fact store.
 ///
 /// Lazily reads facts from disk, yielding only those at or after the starting timestamp.
-pub struct FactIterator<E, V, S> {
-    reader: std::io::BufReader<std::fs::File>,
-    since: DateTime<Utc>,
-    line_buffer: String,
-    found_starting_point: bool,
-    _phantom: std::marker::PhantomData<(E, V, S)>,
+/// Only facts that were in the store when the iterator was created are
+/// yielded; see [`TryFactIterator::refresh`] to carry on with later ones.
+///
+/// Iteration ends at the first line that cannot be read or parsed, which is
+/// indistinguishable from reaching the end of the store. Use
+/// [`FactStore::try_iter_from`] when that difference matters.
+pub struct FactIterator<E, V, S, C = JsonCodec> {
+    inner: TryFactIterator<E, V, S, C>,
+}
+
+impl<E, V, S, C> FactIterator<E, V, S, C> {
+    /// Position just after the last fact yielded.
+    ///
+    /// See [`TryFactIterator::cursor`].
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.inner.cursor
+    }
 }
 
-impl<E, V, S> FactIterator<E, V, S>
+impl<E, V, S, C> FactIterator<E, V, S, C>
 where
+    C: Codec,
     E: DeserializeOwned + Clone,
     V: DeserializeOwned + Clone,
     S: DeserializeOwned + Clone,
 {
-    fn new(path: PathBuf, since: DateTime<Utc>) -> Self {
-        let file = std::fs::File::open(&path).ok();
-        let reader = file.map(std::io::BufReader::new).unwrap_or_else(|| {
-            // Return empty reader if file doesn't exist
-            std::io::BufReader::new(std::fs::File::open("/dev/null").unwrap())
-        });
+    /// Sequence number of the last fact yielded.
+    ///
+    /// See [`TryFactIterator::seq`].
+    pub fn seq(&self) -> Option<u64> {
+        self.inner.seq()
+    }
+
+    /// Carry on with facts appended since the iterator was created.
+    ///
+    /// See [`TryFactIterator::refresh`].
+    pub fn refresh(&mut self) {
+        self.inner.refresh();
+    }
 
+    /// Keep other processes from writing to the store until the iterator
+    /// is dropped.
+    ///
+    /// See [`TryFactIterator::with_shared_lock`].
+    pub fn with_shared_lock(self) -> Self {
         Self {
-            reader,
-            since,
-            line_buffer: String::with_capacity(1024),
-            found_starting_point: false,
-            _phantom: std::marker::PhantomData,
+            inner: self.inner.with_shared_lock(),
         }
     }
 }
 
-impl<E, V, S> Iterator for FactIterator<E, V, S>
+impl<E, V, S, C> Iterator for FactIterator<E, V, S, C>
 where
+    C: Codec,
     E: DeserializeOwned + Clone,
     V: DeserializeOwned + Clone,
     S: DeserializeOwned + Clone,
@@ -240,94 +1043,620 @@ where
     type Item = Fact<E, V, S>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            self.line_buffer.clear();
-
-            // Read next line
-            let bytes_read = self.reader.read_line(&mut self.line_buffer).ok()?;
-            if bytes_read == 0 {
-                return None; // EOF
-            }
+        // TryFactIterator is fused after an error, so this stops for good
+        self.inner.next()?.ok()
+    }
+}
 
-            // Parse fact
-            let fact: Fact<E, V, S> = serde_json::from_str(&self.line_buffer).ok()?;
+/// Fallible iterator over facts in a fact store.
+///
+/// Like [`FactIterator`], but yields every read or parse failure as a
+/// [`StoreError::ReadFailed`] carrying the line number and byte offset of the
+/// offending line. Iteration ends after the first error. Blank lines are
+/// skipped, and an incomplete last line is treated as a write in progress
+/// rather than an error.
+///
+/// The iterator sees the store as it was when it was created: the length
+/// of the file being appended to is taken then, while no append is half
+/// way through, and nothing past it is read. A scan therefore yields
+/// exactly the facts that existed when it started, however many are
+/// appended meanwhile. [`TryFactIterator::refresh`] moves the end forward.
+pub struct TryFactIterator<E, V, S, C = JsonCodec> {
+    codec: C,
+    /// File currently being read
+    path: PathBuf,
+    /// Later segment files still to be read
+    remaining: VecDeque<PathBuf>,
+    /// `None` once the file is exhausted, missing, or an error was reported
+    reader: Option<std::io::BufReader<StreamFile>>,
+    /// Failure to open the file, reported by the first call to `next`
+    open_error: Option<std::io::Error>,
+    since: DateTime<Utc>,
+    /// Byte offset of the next line to read
+    offset: u64,
+    line_buffer: Vec<u8>,
+    /// Facts decoded from a framed batch that have not been yielded yet
+    pending: VecDeque<Fact<E, V, S>>,
+    found_starting_point: bool,
+    /// Whether the files are segments, numbered in their names
+    segmented: bool,
+    /// Sequence number of the current segment, 0 for an unsegmented store
+    segment: usize,
+    /// The line the pending facts came from
+    line: LinePosition,
+    /// Position after the last fact yielded
+    cursor: Option<Cursor>,
+    /// Cursor to check the first line against before resuming after it
+    resume: Option<Cursor>,
+    /// How to find the sequence number of the first fact read
+    origin: Option<Origin>,
+    /// That sequence number, counted the first time it is asked for
+    first_seq: OnceLock<Option<u64>>,
+    /// Number of facts read before the last one yielded
+    taken: Option<u64>,
+    /// Facts still to skip before yielding any
+    skip: u64,
+    /// Length of the last file when the iterator was created or refreshed.
+    /// `None` when following, or if that file is compressed
+    end: Option<u64>,
+    /// Shared lock on the last file, if asked to hold one
+    lock: Option<std::fs::File>,
+    /// Set when following the store: wait at the end instead of stopping
+    follow: Option<Follow>,
+    /// Set once an error has ended the iteration
+    stopped: bool,
+}
 
-            // If we haven't found starting point yet, check timestamp
-            if !self.found_starting_point {
-                if fact.timestamp() >= &self.since {
-                    self.found_starting_point = true;
-                    return Some(fact);
-                }
-                // Skip this fact, continue searching
-                continue;
-            }
+/// A line start in the first file an iterator reads, with the sequence
+/// number of the first fact at or after it.
+///
+/// The facts from there up to where the iterator starts are only counted if
+/// a sequence number is asked for.
+struct Origin {
+    path: PathBuf,
+    from: u64,
+    seq: u64,
+    /// Byte offset the iterator starts at
+    to: u64,
+}
 
-            // We're past starting point, return all facts
-            return Some(fact);
-        }
-    }
+/// Where a following iterator stands with respect to segment rolls.
+struct Follow {
+    /// Segment found to exist after the current one was read to its end
+    rolled: Option<PathBuf>,
 }
 
-// ============================================================================
-// Tests
-// ============================================================================
+impl<E, V, S, C> TryFactIterator<E, V, S, C>
+where
+    C: Codec,
+    E: DeserializeOwned + Clone,
+    V: DeserializeOwned + Clone,
+    S: DeserializeOwned + Clone,
+{
+    /// Read from the first fact at or after `since`, searching the first
+    /// file between the byte offsets `low` and `high`.
+    fn new(
+        codec: C,
+        files: Vec<PathBuf>,
+        segmented: bool,
+        since: DateTime<Utc>,
+        (low, high): (u64, Option<u64>),
+        anchor: Option<(u64, u64)>,
+    ) -> Self {
+        let path = files.first().cloned().unwrap_or_default();
+        let offset = seek::seek_offset(&codec, &path, since, low, high).unwrap_or(low);
+        Self::open(codec, files, segmented, since, offset, anchor)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{assert_fact_value_format, Fact, Operation};
-    use serde::{Deserialize, Serialize};
-    use tempfile::NamedTempFile;
+    /// Resume right after the fact `cursor` was taken on.
+    fn resuming(
+        codec: C,
+        files: Vec<PathBuf>,
+        segmented: bool,
+        cursor: Cursor,
+        anchor: Option<(u64, u64)>,
+    ) -> Self {
+        let mut iter = Self::open(
+            codec,
+            files,
+            segmented,
+            DateTime::<Utc>::MIN_UTC,
+            cursor.offset(),
+            anchor,
+        );
+        iter.cursor = Some(cursor);
+        iter.resume = Some(cursor);
+        iter.taken = Some(cursor.consumed() as u64 - 1);
+        iter
+    }
 
-    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-    #[serde(tag = "t", content = "v")]
-    enum TestValue {
-        Count(u32),
+    /// An iterator that only reports `error`.
+    fn failed(codec: C, error: std::io::Error) -> Self {
+        let mut iter = Self::open(codec, Vec::new(), false, DateTime::<Utc>::MIN_UTC, 0, None);
+        iter.open_error = Some(error);
+        iter
     }
 
-    // Validate format in a test
-    #[test]
-    fn test_value_format() {
-        assert_fact_value_format!(TestValue::Count(42));
+    /// Read `files` in order, starting at `offset` in the first.
+    ///
+    /// `anchor` is a line start in the first file at or before `offset` and
+    /// the sequence number of the first fact at or after it, if known.
+    fn open(
+        codec: C,
+        files: Vec<PathBuf>,
+        segmented: bool,
+        since: DateTime<Utc>,
+        offset: u64,
+        anchor: Option<(u64, u64)>,
+    ) -> Self {
+        let mut remaining = VecDeque::from(files);
+        let path = remaining.pop_front().unwrap_or_default();
+        let origin = anchor.map(|(from, seq)| Origin {
+            path: path.clone(),
+            from,
+            seq,
+            to: offset,
+        });
+
+        let mut iter = Self {
+            codec,
+            path,
+            remaining,
+            reader: None,
+            open_error: None,
+            since,
+            offset,
+            line_buffer: Vec::with_capacity(1024),
+            pending: VecDeque::new(),
+            found_starting_point: false,
+            segmented,
+            segment: 0,
+            line: LinePosition::default(),
+            cursor: None,
+            resume: None,
+            origin,
+            first_seq: OnceLock::new(),
+            taken: None,
+            skip: 0,
+            end: None,
+            lock: None,
+            follow: None,
+            stopped: false,
+        };
+        iter.open_at(offset);
+        if let Some(origin) = &mut iter.origin {
+            // A compressed file may end before `offset`
+            origin.to = iter.offset;
+        }
+        iter.capture_end();
+        iter
     }
 
-    fn create_test_facts() -> Vec<Fact<String, TestValue, String>> {
-        vec![
-            Fact::new(
-                "item1".to_string(),
-                TestValue::Count(1),
-                "2024-01-15T10:00:00Z".parse().unwrap(),
-                "source1".to_string(),
-                Operation::Assert,
-            ),
-            Fact::new(
-                "item2".to_string(),
-                TestValue::Count(2),
-                "2024-01-15T10:01:00Z".parse().unwrap(),
-                "source1".to_string(),
-                Operation::Assert,
-            ),
-            Fact::new(
-                "item3".to_string(),
-                TestValue::Count(3),
-                "2024-01-15T10:02:00Z".parse().unwrap(),
-                "source1".to_string(),
-                Operation::Assert,
-            ),
-        ]
+    /// Position just after the last fact yielded.
+    ///
+    /// `None` until the first fact has been yielded, unless the iterator
+    /// was itself resumed from a cursor. Pass it to
+    /// [`FactStore::iter_from_cursor`] to carry on from there later.
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.cursor
     }
 
-    #[test]
-    fn test_open_or_create() {
-        let temp = NamedTempFile::new().unwrap();
-        let store = FactStore::<String, TestValue, String>::open_or_create(temp.path()).unwrap();
+    /// Sequence number of the last fact yielded.
+    ///
+    /// `None` until the first fact has been yielded, unless the iterator was
+    /// resumed from a cursor, in which case it is the cursor's fact. Also
+    /// `None` if the facts before the iterator's starting point could not be
+    /// counted.
+    ///
+    /// Iterators started from a sequence number or at the start of the store
+    /// know it right away. Others count the facts before their starting
+    /// point the first time this is called, from the closest point where the
+    /// index or the segment manifest knows the sequence number.
+    pub fn seq(&self) -> Option<u64> {
+        let taken = self.taken?;
+        let first_seq = self.first_seq.get_or_init(|| {
+            let origin = self.origin.as_ref()?;
+            common::count_facts(&self.codec, &origin.path, origin.from, origin.to)
+                .ok()
+                .map(|counted| origin.seq + counted)
+        });
+        first_seq.map(|first_seq| first_seq + taken)
+    }
 
-        assert_eq!(store.latest_timestamp(), None);
+    /// Keep waiting for new facts at the end of the store instead of ending.
+    ///
+    /// `next` then returns `None` whenever it has caught up, and picks up
+    /// where it left off on the next call, including in the middle of a
+    /// line that was only partly written. Segmented stores are followed
+    /// across segment rolls.
+    pub(crate) fn following(mut self) -> Self {
+        self.follow = Some(Follow { rolled: None });
+        self.end = None;
+        self
     }
 
-    #[test]
-    fn test_append_and_latest_timestamp() {
-        let temp = NamedTempFile::new().unwrap();
+    /// Carry on with facts appended since the iterator was created.
+    ///
+    /// Moves the end of the iteration to the end of the store as it is now,
+    /// including segments started since, so that once the iterator has
+    /// caught up, further calls to `next` yield the facts appended in the
+    /// meantime. It picks up where it stopped, whether or not it had
+    /// reached the old end. Does nothing once an error has ended the
+    /// iteration.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use stainless_facts::FactStore;
+    /// # fn example(store: FactStore<String, String, String>) -> Result<(), stainless_facts::StoreError> {
+    /// let mut facts = store.try_iter();
+    /// for fact in facts.by_ref() {
+    ///     println!("{:?}", fact?);
+    /// }
+    /// // ... later, read only what was appended since
+    /// facts.refresh();
+    /// for fact in facts {
+    ///     println!("{:?}", fact?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn refresh(&mut self) {
+        if self.stopped || self.follow.is_some() || self.path.as_os_str().is_empty() {
+            return;
+        }
+        if self.segmented {
+            let mut last = self.remaining.back().unwrap_or(&self.path).clone();
+            while let Some(next) = segment::next_segment(&last) {
+                self.remaining.push_back(next.clone());
+                last = next;
+            }
+        }
+        if self.reader.is_none() {
+            // The file was missing or had been read to its end
+            self.open_at(self.offset);
+        }
+        self.capture_end();
+    }
+
+    /// Keep other processes from writing to the store until the iterator
+    /// is dropped.
+    ///
+    /// Takes a shared lock on the file being appended to, waiting for an
+    /// append in progress to finish. Appends from this or any other process
+    /// then wait for the lock, or fail with
+    /// [`WriteError::AlreadyLocked`], and
+    /// crash recovery on open cannot repair the end of the file while the
+    /// iterator is reading it. Without the lock an iterator still stops at
+    /// the end it was created with; the lock also keeps what lies before
+    /// that end from being rewritten.
+    pub fn with_shared_lock(mut self) -> Self {
+        let last = self.remaining.back().unwrap_or(&self.path);
+        if last.as_os_str().is_empty() {
+            return self;
+        }
+        match common::lock_shared(last) {
+            Ok(lock) => self.lock = lock,
+            Err(e) => {
+                self.open_error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    /// Fix the end of the iteration at the length of the last file.
+    fn capture_end(&mut self) {
+        let last = self.remaining.back().unwrap_or(&self.path);
+        if self.follow.is_some() || last.as_os_str().is_empty() {
+            return;
+        }
+        match common::committed_len(last) {
+            Ok(end) => self.end = end,
+            Err(e) => {
+                self.open_error.get_or_insert(e);
+            }
+        }
+    }
+
+    /// Whether the iterator has read up to `offset` in the last file and
+    /// stopped there, leaving later facts for [`TryFactIterator::refresh`].
+    fn at_end(&self, offset: u64) -> bool {
+        self.remaining.is_empty() && self.end.is_some_and(|end| offset >= end)
+    }
+
+    /// Whether an error has ended the iteration for good.
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// Open the current file positioned at `offset`.
+    ///
+    /// A compressed segment cannot seek, so it is read up to `offset`.
+    fn open_at(&mut self, offset: u64) {
+        let opened = compression::open_segment(&self.path).and_then(|(path, mut file)| {
+            let offset = match file.as_plain_mut() {
+                Some(plain) => plain.seek(SeekFrom::Start(offset))?,
+                None => std::io::copy(&mut (&mut file).take(offset), &mut std::io::sink())?,
+            };
+            Ok((path, file, offset))
+        });
+        self.offset = offset;
+        match opened {
+            Ok((path, file, offset)) => {
+                self.segment = if self.segmented {
+                    segment::sequence(&path).unwrap_or(0)
+                } else {
+                    0
+                };
+                self.path = path;
+                self.offset = offset;
+                self.reader = Some(std::io::BufReader::new(file));
+            }
+            // A store or segment that has never been written to is simply empty
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.reader = None,
+            Err(e) => {
+                self.reader = None;
+                self.open_error = Some(e);
+            }
+        }
+    }
+
+    /// Move on to the next segment file, returning `false` if there is none.
+    fn next_file(&mut self) -> bool {
+        let Some(path) = self.remaining.pop_front() else {
+            self.reader = None;
+            return false;
+        };
+        self.path = path;
+        self.open_at(0);
+        true
+    }
+
+    /// Stop iterating for good.
+    fn stop(&mut self) {
+        self.reader = None;
+        self.remaining.clear();
+        self.stopped = true;
+    }
+
+    /// Handle the end of the current file: move on, wait, or end.
+    ///
+    /// Returns `true` if there is more to read right away.
+    fn end_of_file(&mut self) -> bool {
+        if !self.remaining.is_empty() {
+            return self.next_file();
+        }
+        let Some(follow) = &mut self.follow else {
+            self.reader = None;
+            return false;
+        };
+
+        if let Some(next) = follow.rolled.take() {
+            self.remaining.push_back(next);
+            return self.next_file();
+        }
+        // Once the next segment exists nothing more is written to this one,
+        // but it may have grown since we reached its end, so read it again
+        if self.segmented {
+            follow.rolled = segment::next_segment(&self.path);
+            return follow.rolled.is_some();
+        }
+        false
+    }
+
+    /// Check the line just read against the cursor being resumed from, and
+    /// skip the facts on it up to and including the cursor's.
+    fn resume(&mut self, cursor: Cursor, offset: u64) -> Result<(), StoreError> {
+        let decoded = common::decode_line(&self.codec, &self.line_buffer, &mut self.pending);
+        if decoded.is_err()
+            || self.segment != cursor.segment()
+            || !cursor.matches(&self.line_buffer, &self.pending)
+        {
+            return Err(self.mismatch(cursor));
+        }
+        self.line
+            .start(offset, &self.line_buffer, self.pending.len());
+        self.pending.drain(..cursor.consumed());
+        Ok(())
+    }
+
+    /// The fact `cursor` was taken on is gone: stop iterating.
+    fn mismatch(&mut self, cursor: Cursor) -> StoreError {
+        self.pending.clear();
+        self.stop();
+        StoreError::CursorMismatch {
+            offset: cursor.offset(),
+        }
+    }
+
+    /// Build the error for the line at `offset` and stop iterating.
+    fn fail(&mut self, offset: u64, source: ReadError) -> StoreError {
+        self.stop();
+        // Line numbers are only needed on failure, so count them lazily
+        let line = common::line_number(&self.path, offset, C::DELIMITER).unwrap_or(0);
+        StoreError::ReadFailed {
+            line,
+            offset,
+            source,
+        }
+    }
+}
+
+impl<E, V, S, C> Iterator for TryFactIterator<E, V, S, C>
+where
+    C: Codec,
+    E: DeserializeOwned + Clone,
+    V: DeserializeOwned + Clone,
+    S: DeserializeOwned + Clone,
+{
+    type Item = Result<Fact<E, V, S>, StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(e) = self.open_error.take() {
+                self.stop();
+                return Some(Err(StoreError::Io(e)));
+            }
+
+            if let Some(fact) = self.pending.pop_front() {
+                if self.skip > 0 {
+                    self.skip -= 1;
+                    continue;
+                }
+                // If we haven't found starting point yet, check timestamp
+                if !self.found_starting_point {
+                    if fact.timestamp() < &self.since {
+                        continue; // Skip this fact, continue searching
+                    }
+                    self.found_starting_point = true;
+                }
+                let left = self.pending.len();
+                self.cursor = Some(self.line.cursor(self.segment, left, *fact.timestamp()));
+                self.taken = Some(self.line.taken(left));
+                return Some(Ok(fact));
+            }
+
+            if self.reader.is_some() && self.at_end(self.offset) {
+                if let Some(cursor) = self.resume.take() {
+                    return Some(Err(self.mismatch(cursor)));
+                }
+                // Appended after the iterator was created or last refreshed
+                return None;
+            }
+            let Some(reader) = self.reader.as_mut() else {
+                if self.stopped {
+                    return None;
+                }
+                if let Some(cursor) = self.resume.take() {
+                    return Some(Err(self.mismatch(cursor)));
+                }
+                // Nothing open: the file was missing or is used up
+                if self.next_file() {
+                    continue;
+                }
+                // A followed store may have been created in the meantime
+                if self.follow.is_some() && !self.path.as_os_str().is_empty() {
+                    self.open_at(self.offset);
+                    if self.reader.is_some() || self.open_error.is_some() {
+                        continue;
+                    }
+                }
+                return None;
+            };
+            let offset = self.offset;
+
+            // Read next line
+            self.line_buffer.clear();
+            let bytes_read = match reader.read_until(C::DELIMITER, &mut self.line_buffer) {
+                Ok(0) => {
+                    if let Some(cursor) = self.resume.take() {
+                        return Some(Err(self.mismatch(cursor)));
+                    }
+                    // End of this file, continue with the next segment
+                    if self.end_of_file() {
+                        continue;
+                    }
+                    return None; // EOF
+                }
+                Ok(bytes_read) => bytes_read,
+                Err(e) => return Some(Err(self.fail(offset, ReadError::Io(e)))),
+            };
+            self.offset += bytes_read as u64;
+
+            let partial = self.line_buffer.last() != Some(&C::DELIMITER);
+            let past_end =
+                self.remaining.is_empty() && self.end.is_some_and(|end| self.offset > end);
+            if (self.follow.is_some() && partial) || past_end {
+                // Still being written, or running past the end we stop at:
+                // read the whole line again later
+                if let Err(e) = reader.seek(SeekFrom::Start(offset)) {
+                    return Some(Err(self.fail(offset, ReadError::Io(e))));
+                }
+                self.offset = offset;
+                return None;
+            }
+
+            if let Some(cursor) = self.resume.take() {
+                if let Err(e) = self.resume(cursor, offset) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
+            if common::is_blank(&self.line_buffer) {
+                continue; // Skip empty lines
+            }
+
+            // Parse the fact, or every fact of a framed batch
+            if let Err(e) = common::decode_line(&self.codec, &self.line_buffer, &mut self.pending) {
+                if self.line_buffer.last() != Some(&C::DELIMITER) {
+                    // An uncommitted write: nothing in it is visible yet
+                    self.stop();
+                    return None;
+                }
+                return Some(Err(self.fail(offset, e)));
+            }
+            self.line
+                .start(offset, &self.line_buffer, self.pending.len());
+        }
+    }
+}
+
+/// Fsync the stream file at `path`, if it exists.
+pub(crate) fn sync_file(path: &Path) -> std::io::Result<()> {
+    // Opened for writing, which Windows needs to flush a file
+    match std::fs::OpenOptions::new().append(true).open(path) {
+        Ok(file) => file.sync_all(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Length of the file at `path`, or 0 if it does not exist yet.
+fn file_len(path: &Path) -> std::io::Result<u64> {
+    match std::fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        append_garbage, at, create_many_facts, create_test_facts, fact, TestValue,
+    };
+    use crate::{assert_fact_value_format, Fact, Operation};
+    use serde::Serialize;
+    use tempfile::NamedTempFile;
+
+    const FRAMED: LineFormat = LineFormat {
+        framed: true,
+        checksums: false,
+    };
+
+    // Validate format in a test
+    #[test]
+    fn test_value_format() {
+        assert_fact_value_format!(TestValue::Count(42));
+    }
+
+    #[test]
+    fn test_open_or_create() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::<String, TestValue, String>::open_or_create(temp.path()).unwrap();
+
+        assert_eq!(store.latest_timestamp(), None);
+    }
+
+    #[test]
+    fn test_append_and_latest_timestamp() {
+        let temp = NamedTempFile::new().unwrap();
         let store = FactStore::open_or_create(temp.path()).unwrap();
 
         let facts = create_test_facts();
@@ -354,6 +1683,27 @@ mod tests {
         assert!(matches!(result, Err(StoreError::TimestampOrdering { .. })));
     }
 
+    #[test]
+    fn test_unordered_batch_is_rejected() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+
+        let facts = create_test_facts();
+        let unordered = [facts[1].clone(), facts[0].clone()];
+
+        // Each fact is newer than the (empty) store, but not than the one before it
+        let result = store.append_batch(&unordered);
+        assert!(matches!(result, Err(StoreError::TimestampOrdering { .. })));
+        assert_eq!(store.iter().count(), 0);
+
+        let store = FactStore::open_with(
+            FactStoreOptions::new(temp.path()).with_ordering(OrderingPolicy::Unchecked),
+        )
+        .unwrap();
+        store.append_batch(&unordered).unwrap();
+        assert_eq!(store.iter().collect::<Vec<_>>(), unordered);
+    }
+
     #[test]
     fn test_iter_all() {
         let temp = NamedTempFile::new().unwrap();
@@ -457,4 +1807,1105 @@ mod tests {
         let facts: Vec<_> = store.iter().collect();
         assert_eq!(facts.len(), 0);
     }
+
+    #[test]
+    fn test_indexed_iter_from_middle() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let store = FactStore::open_or_create_with_index(&path, 8).unwrap();
+
+        let facts = create_many_facts(100);
+        store.append_batch(&facts[..50]).unwrap();
+        store.append_batch(&facts[50..]).unwrap();
+
+        let since = *facts[37].timestamp();
+        let read_facts: Vec<_> = store.iter_from(since).collect();
+
+        assert_eq!(read_facts, facts[37..]);
+    }
+
+    #[test]
+    fn test_iter_from_middle_of_large_store() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+
+        let facts = create_many_facts(2000);
+        store.append_batch(&facts).unwrap();
+
+        let since = *facts[1234].timestamp();
+        let read_facts: Vec<_> = store.iter_from(since).collect();
+
+        assert_eq!(read_facts, facts[1234..]);
+    }
+
+    #[test]
+    fn test_index_sidecar_written_on_append() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let store = FactStore::open_or_create_with_index(&path, 8).unwrap();
+
+        store.append_batch(&create_many_facts(20)).unwrap();
+
+        let sidecar = std::fs::read_to_string(TimestampIndex::sidecar_path(&path)).unwrap();
+        assert_eq!(sidecar.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_index_rebuilt_when_missing() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let facts = create_many_facts(40);
+
+        {
+            let store: FactStore<String, TestValue, String> =
+                FactStore::open_or_create(&path).unwrap();
+            store.append_batch(&facts).unwrap();
+        }
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_or_create_with_index(&path, 8).unwrap();
+
+        assert!(TimestampIndex::sidecar_path(&path).exists());
+        let since = *facts[21].timestamp();
+        let read_facts: Vec<_> = store.iter_from(since).collect();
+        assert_eq!(read_facts, facts[21..]);
+    }
+
+    #[test]
+    fn test_reopen_ignores_torn_trailing_line() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts = create_test_facts();
+
+        {
+            let store: FactStore<String, TestValue, String> =
+                FactStore::open_or_create(temp.path()).unwrap();
+            store.append_batch(&facts[..2]).unwrap();
+        }
+
+        // Simulate a crash halfway through writing the third fact
+        append_garbage(temp.path(), b"[\"item3\",{\"t\":\"Co");
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_or_create(temp.path()).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[1].timestamp()));
+    }
+
+    #[test]
+    fn test_reopen_falls_back_to_scan_for_unparseable_tail() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts = create_test_facts();
+
+        {
+            let store: FactStore<String, TestValue, String> =
+                FactStore::open_or_create(temp.path()).unwrap();
+            store.append_batch(&facts).unwrap();
+        }
+
+        append_garbage(temp.path(), b"not a fact\n");
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_or_create(temp.path()).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[2].timestamp()));
+    }
+
+    #[test]
+    fn test_try_iter_reports_line_and_offset_of_bad_line() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+
+        let facts = create_test_facts();
+        store.append_batch(&facts[..2]).unwrap();
+        let bad_offset = std::fs::metadata(temp.path()).unwrap().len();
+        append_garbage(temp.path(), b"not a fact\n");
+
+        let results: Vec<_> = store.try_iter().collect();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[..2].iter().all(Result::is_ok));
+        match &results[2] {
+            Err(StoreError::ReadFailed {
+                line,
+                offset,
+                source: ReadError::Deserialization(_),
+            }) => {
+                assert_eq!(*line, 3);
+                assert_eq!(*offset, bad_offset);
+            }
+            other => panic!("Expected ReadFailed, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_try_iter_stops_after_error() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+
+        let facts = create_test_facts();
+        store.append(facts[0].clone()).unwrap();
+        append_garbage(temp.path(), b"not a fact\n");
+        store.append(facts[1].clone()).unwrap();
+
+        let results: Vec<_> = store.try_iter().collect();
+
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_try_iter_skips_blank_lines() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+
+        let facts = create_test_facts();
+        store.append(facts[0].clone()).unwrap();
+        append_garbage(temp.path(), b"\n  \n");
+        store.append(facts[1].clone()).unwrap();
+
+        let read_facts: Result<Vec<_>, _> = store.try_iter().collect();
+
+        assert_eq!(read_facts.unwrap(), facts[..2]);
+    }
+
+    #[test]
+    fn test_iter_ends_at_bad_line() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+
+        let facts = create_test_facts();
+        store.append(facts[0].clone()).unwrap();
+        append_garbage(temp.path(), b"not a fact\n");
+        store.append(facts[1].clone()).unwrap();
+
+        let read_facts: Vec<_> = store.iter().collect();
+
+        assert_eq!(read_facts, facts[..1]);
+    }
+
+    #[test]
+    fn test_open_quarantines_torn_write_by_default() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts = create_test_facts();
+
+        {
+            let store: FactStore<String, TestValue, String> =
+                FactStore::open_or_create(temp.path()).unwrap();
+            store.append_batch(&facts[..2]).unwrap();
+        }
+        append_garbage(temp.path(), b"[\"item3\",{\"t\":\"Co");
+
+        // Appending after reopening must not glue onto the torn record
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+        store.append(facts[2].clone()).unwrap();
+
+        let read_facts: Result<Vec<_>, _> = store.try_iter().collect();
+        assert_eq!(read_facts.unwrap(), facts);
+        let quarantined = std::fs::read(crate::io::recovery::quarantine_path(temp.path())).unwrap();
+        assert_eq!(quarantined, b"[\"item3\",{\"t\":\"Co\n");
+    }
+
+    #[test]
+    fn test_open_with_recovery_reports_truncation() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts = create_test_facts();
+
+        {
+            let store: FactStore<String, TestValue, String> =
+                FactStore::open_or_create(temp.path()).unwrap();
+            store.append_batch(&facts).unwrap();
+        }
+        let valid_len = std::fs::metadata(temp.path()).unwrap().len();
+        append_garbage(temp.path(), b"[\"item4\"");
+
+        let (store, report) = FactStore::<String, TestValue, String>::open_with_recovery(
+            temp.path(),
+            RecoveryPolicy::Truncate,
+        )
+        .unwrap();
+
+        assert_eq!(report.valid_len, valid_len);
+        assert_eq!(report.removed_bytes, 8);
+        assert_eq!(report.quarantine_path, None);
+        assert_eq!(store.iter().count(), 3);
+    }
+
+    /// Write the first `len` bytes of a framed batch, as a crash would.
+    fn append_torn_batch(path: &Path, facts: &[Fact<String, TestValue, String>], len: usize) {
+        let buffer = common::serialize_batch(&JsonCodec, facts, FRAMED).unwrap();
+        append_garbage(path, &buffer[..len]);
+    }
+
+    #[test]
+    fn test_framed_batches_round_trip() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path())
+            .unwrap()
+            .with_batch_framing(true);
+
+        let facts = create_many_facts(10);
+        store.append_batch(&facts[..4]).unwrap();
+        store.append_batch(&facts[4..]).unwrap();
+
+        let contents = std::fs::read_to_string(temp.path()).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+        // Starting in the middle of a batch skips its earlier facts
+        let read_facts: Vec<_> = store.iter_from(*facts[6].timestamp()).collect();
+        assert_eq!(read_facts, facts[6..]);
+    }
+
+    #[test]
+    fn test_framed_and_unframed_batches_mix() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(6);
+
+        let store = FactStore::open_or_create(temp.path()).unwrap();
+        store.append_batch(&facts[..2]).unwrap();
+        let store = store.with_batch_framing(true);
+        store.append_batch(&facts[2..4]).unwrap();
+        let store = store.with_batch_framing(false);
+        store.append_batch(&facts[4..]).unwrap();
+
+        let reopened: FactStore<String, TestValue, String> =
+            FactStore::open_or_create(temp.path()).unwrap();
+        assert_eq!(reopened.latest_timestamp(), Some(*facts[5].timestamp()));
+        let read_facts: Result<Vec<_>, _> = reopened.try_iter().collect();
+        assert_eq!(read_facts.unwrap(), facts);
+    }
+
+    #[test]
+    fn test_uncommitted_framed_batch_is_not_visible() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path())
+            .unwrap()
+            .with_batch_framing(true);
+
+        let facts = create_many_facts(6);
+        store.append_batch(&facts[..3]).unwrap();
+        // All but the closing bytes of the second batch reached the disk
+        append_torn_batch(temp.path(), &facts[3..], 150);
+
+        let read_facts: Result<Vec<_>, _> = store.try_iter().collect();
+        assert_eq!(read_facts.unwrap(), facts[..3]);
+
+        let reader = crate::FactStreamReader::open(temp.path()).unwrap();
+        let read_facts: Result<Vec<Fact<String, TestValue, String>>, _> = reader.collect();
+        assert_eq!(read_facts.unwrap(), facts[..3]);
+    }
+
+    #[test]
+    fn test_open_drops_uncommitted_framed_batch() {
+        let temp = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(6);
+
+        {
+            let store = FactStore::open_or_create(temp.path())
+                .unwrap()
+                .with_batch_framing(true);
+            store.append_batch(&facts[..3]).unwrap();
+        }
+        let valid_len = std::fs::metadata(temp.path()).unwrap().len();
+        append_torn_batch(temp.path(), &facts[3..], 150);
+
+        let (store, report) = FactStore::<String, TestValue, String>::open_with_recovery(
+            temp.path(),
+            RecoveryPolicy::Truncate,
+        )
+        .unwrap();
+
+        assert_eq!(report.valid_len, valid_len);
+        assert_eq!(report.removed_bytes, 150);
+        assert_eq!(store.latest_timestamp(), Some(*facts[2].timestamp()));
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts[..3]);
+    }
+
+    #[test]
+    fn test_checksum_mismatch_is_reported_and_verified() {
+        let temp = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp.path())
+            .unwrap()
+            .with_checksums(true);
+
+        let facts = create_test_facts();
+        store.append_batch(&facts).unwrap();
+        // Change the second count from 2 to 5 without touching its checksum
+        let contents = std::fs::read_to_string(temp.path()).unwrap();
+        let second_line = contents.find('\n').unwrap() as u64 + 1;
+        std::fs::write(temp.path(), contents.replace("\"v\":2}", "\"v\":5}")).unwrap();
+
+        let read_facts: Vec<_> = store.try_iter().collect();
+        assert_eq!(read_facts.len(), 2);
+        assert!(matches!(
+            read_facts[1],
+            Err(StoreError::ReadFailed {
+                line: 2,
+                source: ReadError::ChecksumMismatch { .. },
+                ..
+            })
+        ));
+
+        let report = store.verify().unwrap();
+        assert_eq!(report.facts, 2);
+        assert_eq!(report.checksummed_lines, 3);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].offset, second_line);
+    }
+
+    #[test]
+    fn test_segmented_store_rolls_and_reads_across_segments() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(500);
+        let facts = create_many_facts(40);
+
+        {
+            let store = FactStore::open_segmented(dir.path(), policy).unwrap();
+            for batch in facts.chunks(3) {
+                store.append_batch(batch).unwrap();
+            }
+        }
+
+        let segment_files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("facts".as_ref()))
+            .count();
+        assert!(segment_files > 2);
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_segmented(dir.path(), policy).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[39].timestamp()));
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+        let read_facts: Vec<_> = store.iter_from(*facts[25].timestamp()).collect();
+        assert_eq!(read_facts, facts[25..]);
+        assert!(store.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_segmented_store_shared_by_two_writers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(200);
+        let facts = create_many_facts(40);
+        let ours = FactStore::open_segmented(dir.path(), policy).unwrap();
+        let theirs = FactStore::open_segmented(dir.path(), policy).unwrap();
+
+        // Each rolls segments the other has been appending to
+        for (i, batch) in facts[..30].chunks(2).enumerate() {
+            let store = if i % 3 == 0 { &theirs } else { &ours };
+            store.append_batch(batch).unwrap();
+        }
+        // Several rolls theirs has not seen
+        for fact in &facts[30..] {
+            ours.append(fact.clone()).unwrap();
+        }
+        assert_eq!(theirs.next_seq().unwrap(), 40);
+        assert_eq!(ours.next_seq().unwrap(), 40);
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_segmented(dir.path(), policy).unwrap();
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+        assert!(store.verify().unwrap().is_ok());
+        for start in [0, 9, 23, 39] {
+            // Only correct if every sealed segment's count in the manifest is
+            let read_facts: Vec<_> = store.iter_from_seq(start).collect();
+            assert_eq!(read_facts, facts[start as usize..]);
+            let read_facts: Vec<_> = store
+                .iter_from(*facts[start as usize].timestamp())
+                .collect();
+            assert_eq!(read_facts, facts[start as usize..]);
+        }
+    }
+
+    /// Facts that all share one timestamp.
+    fn create_tied_facts(count: u32) -> Vec<Fact<String, TestValue, String>> {
+        (0..count)
+            .map(|i| {
+                fact(
+                    format!("item{i}"),
+                    TestValue::Count(i),
+                    at(0),
+                    Operation::Assert,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cursor_resumes_between_facts_with_equal_timestamps() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        let facts = create_tied_facts(8);
+        store.append_batch(&facts[..5]).unwrap();
+
+        let mut iter = store.try_iter();
+        assert!(iter.cursor().is_none());
+        let read: Vec<_> = iter.by_ref().take(2).map(Result::unwrap).collect();
+        assert_eq!(read, facts[..2]);
+        let cursor = iter.cursor().unwrap();
+        assert_eq!(cursor.timestamp(), *facts[1].timestamp());
+
+        // Cursors survive being saved and loaded
+        let saved = serde_json::to_string(&cursor).unwrap();
+        let cursor: Cursor = serde_json::from_str(&saved).unwrap();
+
+        store.append_batch(&facts[5..]).unwrap();
+        let mut resumed = store.try_iter_from_cursor(cursor);
+        assert_eq!(resumed.cursor(), Some(cursor));
+        let read: Result<Vec<_>, _> = resumed.by_ref().collect();
+        assert_eq!(read.unwrap(), facts[2..]);
+
+        // Resuming from the end yields nothing more
+        let end = resumed.cursor().unwrap();
+        assert_eq!(store.iter_from_cursor(end).count(), 0);
+    }
+
+    #[test]
+    fn test_cursor_resumes_inside_framed_batch() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp_file.path())
+            .unwrap()
+            .with_batch_framing(true);
+        let facts = create_tied_facts(6);
+        store.append_batch(&facts[..3]).unwrap();
+        store.append_batch(&facts[3..]).unwrap();
+
+        let mut iter = store.iter();
+        assert_eq!(iter.by_ref().take(4).count(), 4);
+        let cursor = iter.cursor().unwrap();
+
+        let read: Vec<_> = store.iter_from_cursor(cursor).collect();
+        assert_eq!(read, facts[4..]);
+    }
+
+    #[test]
+    fn test_cursor_detects_rewritten_store() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(6);
+        let cursor = {
+            let store = FactStore::open_or_create(temp_file.path()).unwrap();
+            store.append_batch(&facts[..4]).unwrap();
+            let mut iter = store.iter();
+            iter.nth(2).unwrap();
+            iter.cursor().unwrap()
+        };
+
+        // Same timestamps, different facts
+        std::fs::remove_file(temp_file.path()).unwrap();
+        let mut rewritten = facts.clone();
+        rewritten[2] = Fact::new(
+            "other".to_string(),
+            TestValue::Count(99),
+            *facts[2].timestamp(),
+            "source2".to_string(),
+            Operation::Retract,
+        );
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&rewritten).unwrap();
+
+        let mut resumed = store.try_iter_from_cursor(cursor);
+        assert!(matches!(
+            resumed.next(),
+            Some(Err(StoreError::CursorMismatch { .. }))
+        ));
+        assert!(resumed.next().is_none());
+        assert_eq!(store.iter_from_cursor(cursor).count(), 0);
+
+        // Truncated to before the cursor's fact
+        std::fs::remove_file(temp_file.path()).unwrap();
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&facts[..2]).unwrap();
+        assert!(matches!(
+            store.try_iter_from_cursor(cursor).next(),
+            Some(Err(StoreError::CursorMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_cursor_resumes_across_segments() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500)).unwrap();
+        let facts = create_many_facts(40);
+        for batch in facts.chunks(3) {
+            store.append_batch(batch).unwrap();
+        }
+
+        let mut iter = store.iter();
+        assert_eq!(iter.by_ref().take(25).count(), 25);
+        let cursor = iter.cursor().unwrap();
+
+        let read: Result<Vec<_>, _> = store.try_iter_from_cursor(cursor).collect();
+        assert_eq!(read.unwrap(), facts[25..]);
+    }
+
+    #[test]
+    fn test_append_batch_returns_sequence_numbers() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_tied_facts(10);
+        {
+            let store = FactStore::open_or_create(temp_file.path()).unwrap();
+            assert_eq!(store.next_seq().unwrap(), 0);
+            assert_eq!(store.append_batch(&facts[..3]).unwrap(), 0..3);
+            let store = store.with_batch_framing(true);
+            assert_eq!(store.append_batch(&facts[3..5]).unwrap(), 3..5);
+            assert_eq!(store.append_batch(&facts[5..6]).unwrap(), 5..6);
+            assert_eq!(store.append_batch(&[]).unwrap(), 6..6);
+        }
+
+        // Counted again after reopening, including facts written by others
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        assert_eq!(store.append_batch(&facts[6..7]).unwrap(), 6..7);
+        FactStreamWriter::open(temp_file.path())
+            .unwrap()
+            .write_batch(&facts[7..9])
+            .unwrap();
+        assert_eq!(store.append_batch(&facts[9..]).unwrap(), 9..10);
+        assert_eq!(store.next_seq().unwrap(), 10);
+    }
+
+    #[test]
+    fn test_append_checks_ordering_against_other_writers() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(4);
+        let ours = FactStore::open_or_create(temp_file.path()).unwrap();
+        let theirs = FactStore::open_or_create(temp_file.path()).unwrap();
+        ours.append(facts[0].clone()).unwrap();
+
+        // Another writer moves the end of the file past our cache
+        theirs.append(facts[2].clone()).unwrap();
+
+        let result = ours.append(facts[1].clone());
+        assert!(matches!(
+            result,
+            Err(StoreError::TimestampOrdering { latest, .. }) if latest == *facts[2].timestamp()
+        ));
+        assert_eq!(ours.latest_timestamp(), Some(*facts[2].timestamp()));
+
+        assert_eq!(ours.append_batch(&facts[3..]).unwrap(), 2..3);
+        let read: Vec<_> = theirs.iter().collect();
+        assert_eq!(read, [&facts[0], &facts[2], &facts[3]].map(Clone::clone));
+        assert!(ours.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_latest_timestamp_sees_other_writers() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(4);
+        let ours: FactStore<String, TestValue, String> =
+            FactStore::open_or_create(temp_file.path()).unwrap();
+        let reader: FactStore<String, TestValue, String> = FactStore::open_with(
+            FactStoreOptions::new(temp_file.path()).with_mode(OpenMode::ReadOnly),
+        )
+        .unwrap();
+        assert_eq!(ours.latest_timestamp(), None);
+
+        let theirs = FactStore::open_or_create(temp_file.path()).unwrap();
+        theirs.append(facts[0].clone()).unwrap();
+        assert_eq!(ours.latest_timestamp(), Some(*facts[0].timestamp()));
+
+        ours.append(facts[1].clone()).unwrap();
+        FactStreamWriter::open(temp_file.path())
+            .unwrap()
+            .write_batch(&facts[2..3])
+            .unwrap();
+        assert_eq!(ours.latest_timestamp(), Some(*facts[2].timestamp()));
+        assert_eq!(theirs.latest_timestamp(), Some(*facts[2].timestamp()));
+
+        theirs.append(facts[3].clone()).unwrap();
+        reader.refresh().unwrap();
+        assert_eq!(reader.latest_timestamp(), Some(*facts[3].timestamp()));
+    }
+
+    #[test]
+    fn test_concurrent_appends_stay_ordered() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        let clock = AtomicI64::new(0);
+        let appended = Mutex::new(Vec::new());
+
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (store, clock, appended) = (&store, &clock, &appended);
+                scope.spawn(move || {
+                    for round in 0..50 {
+                        // Timestamps are taken before appending, so threads
+                        // race each other to write them
+                        let batch: Vec<_> = (0..3)
+                            .map(|i| {
+                                let second = clock.fetch_add(1, Ordering::SeqCst);
+                                fact(
+                                    format!("thread{thread}-{round}"),
+                                    TestValue::Count(i),
+                                    at(second),
+                                    Operation::Assert,
+                                )
+                            })
+                            .collect();
+                        match store.append_batch(&batch) {
+                            Ok(range) => appended.lock().push((range, batch)),
+                            Err(StoreError::TimestampOrdering { .. }) => {}
+                            Err(e) => panic!("append failed: {e}"),
+                        }
+                    }
+                });
+            }
+        });
+
+        // Every accepted batch holds exactly the sequence numbers it was given
+        let mut appended = appended.into_inner();
+        appended.sort_by_key(|(range, _)| range.start);
+        let mut expected = Vec::new();
+        for (range, batch) in &appended {
+            assert_eq!(range.start, expected.len() as u64);
+            assert_eq!(range.end, range.start + batch.len() as u64);
+            expected.extend(batch.iter().cloned());
+        }
+
+        let read: Vec<_> = store.iter().collect();
+        assert_eq!(read, expected);
+        assert!(read
+            .windows(2)
+            .all(|pair| pair[0].timestamp() <= pair[1].timestamp()));
+        assert!(store.verify().unwrap().is_ok());
+        assert_eq!(store.next_seq().unwrap(), read.len() as u64);
+    }
+
+    #[test]
+    fn test_iter_from_seq_and_seq_on_iteration() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("facts.stream");
+        let facts = create_many_facts(100);
+        for store in [
+            FactStore::open_or_create(&path).unwrap(),
+            FactStore::open_or_create_with_index(&path, 8).unwrap(),
+        ] {
+            let store = store.with_batch_framing(true);
+            if store.next_seq().unwrap() == 0 {
+                for batch in facts.chunks(7) {
+                    store.append_batch(batch).unwrap();
+                }
+            }
+
+            for seq in [0, 1, 37, 99] {
+                let mut iter = store.try_iter_from_seq(seq);
+                assert_eq!(iter.seq(), None);
+                assert_eq!(iter.next().unwrap().unwrap(), facts[seq as usize]);
+                assert_eq!(iter.seq(), Some(seq));
+                assert_eq!(iter.by_ref().count(), 99 - seq as usize);
+                assert_eq!(iter.seq(), Some(99));
+            }
+            assert_eq!(store.iter_from_seq(100).count(), 0);
+
+            // Iterators started elsewhere count up to their start on demand
+            let mut iter = store.iter_from(*facts[37].timestamp());
+            iter.nth(5).unwrap();
+            assert_eq!(iter.seq(), Some(42));
+            let mut resumed = store.iter_from_cursor(iter.cursor().unwrap());
+            assert_eq!(resumed.seq(), Some(42));
+            assert_eq!(resumed.next().unwrap(), facts[43]);
+            assert_eq!(resumed.seq(), Some(43));
+        }
+    }
+
+    #[test]
+    fn test_segmented_store_sequence_numbers() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let facts = create_many_facts(40);
+        {
+            let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500)).unwrap();
+            for (i, batch) in facts.chunks(3).enumerate() {
+                let first = i as u64 * 3;
+                assert_eq!(
+                    store.append_batch(batch).unwrap(),
+                    first..first + batch.len() as u64
+                );
+            }
+        }
+
+        let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500)).unwrap();
+        assert_eq!(store.next_seq().unwrap(), 40);
+
+        let mut iter = store.try_iter_from_seq(25);
+        let read: Result<Vec<_>, _> = iter.by_ref().collect();
+        assert_eq!(read.unwrap(), facts[25..]);
+        assert_eq!(iter.seq(), Some(39));
+
+        let mut iter = store.iter_from(*facts[31].timestamp());
+        iter.next().unwrap();
+        assert_eq!(iter.seq(), Some(31));
+    }
+
+    #[test]
+    fn test_relaxed_durability_appends_and_flushes() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(10);
+        let store = FactStore::open_or_create(temp_file.path())
+            .unwrap()
+            .with_durability(DurabilityPolicy::EveryNBatches(3));
+        for fact in &facts {
+            store.append(fact.clone()).unwrap();
+        }
+        store.flush_durable().unwrap();
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500))
+            .unwrap()
+            .with_durability(DurabilityPolicy::OsBuffered);
+        store.flush_durable().unwrap();
+        for batch in facts.chunks(2) {
+            store.append_batch(batch).unwrap();
+        }
+        store.flush_durable().unwrap();
+        assert!(std::fs::read_dir(dir.path()).unwrap().count() > 2);
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+    }
+
+    #[test]
+    fn test_open_with_must_exist_and_read_only() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("facts.jsonl");
+        for mode in [OpenMode::MustExist, OpenMode::ReadOnly] {
+            let result = FactStore::<String, TestValue, String>::open_with(
+                FactStoreOptions::new(&path).with_mode(mode),
+            );
+            assert!(
+                matches!(result, Err(StoreError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound)
+            );
+        }
+        assert!(!path.exists());
+
+        let facts = create_test_facts();
+        let store = FactStore::open_or_create(&path).unwrap();
+        store.append_batch(&facts[..2]).unwrap();
+        append_garbage(&path, b"[\"item3\",{\"t\":\"Co");
+        let len = std::fs::metadata(&path).unwrap().len();
+
+        let reader: FactStore<String, TestValue, String> =
+            FactStore::open_with(FactStoreOptions::new(&path).with_mode(OpenMode::ReadOnly))
+                .unwrap();
+        assert_eq!(reader.iter().collect::<Vec<_>>(), facts[..2]);
+        assert!(matches!(
+            reader.append(facts[2].clone()),
+            Err(StoreError::ReadOnly)
+        ));
+        reader.flush_durable().unwrap();
+        // Recovery was skipped, so the torn record is still there
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_with(FactStoreOptions::new(&path).with_mode(OpenMode::MustExist))
+                .unwrap();
+        store.append(facts[2].clone()).unwrap();
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+    }
+
+    #[test]
+    fn test_open_with_strictly_increasing_ordering() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(3);
+        let store = FactStore::open_with(
+            FactStoreOptions::new(temp_file.path())
+                .with_ordering(OrderingPolicy::StrictlyIncreasing),
+        )
+        .unwrap();
+        store.append(facts[0].clone()).unwrap();
+
+        assert!(matches!(
+            store.append(facts[0].clone()),
+            Err(StoreError::TimestampOrdering { .. })
+        ));
+        assert!(matches!(
+            store.append_batch(&[facts[1].clone(), facts[1].clone()]),
+            Err(StoreError::TimestampOrdering { .. })
+        ));
+        store.append_batch(&facts[1..]).unwrap();
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+    }
+
+    #[test]
+    fn test_open_with_lock_timeout() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(1);
+        let store: FactStore<String, TestValue, String> = FactStore::open_with(
+            FactStoreOptions::new(temp_file.path())
+                .with_lock_timeout(Duration::from_millis(50))
+                .with_retry_interval(Duration::from_millis(10)),
+        )
+        .unwrap();
+
+        // Another process holds the write lock
+        let other = std::fs::File::open(temp_file.path()).unwrap();
+        fs2::FileExt::lock_exclusive(&other).unwrap();
+        assert!(matches!(
+            store.append(facts[0].clone()),
+            Err(StoreError::Write(WriteError::LockTimeout(_)))
+        ));
+
+        fs2::FileExt::unlock(&other).unwrap();
+        store.append(facts[0].clone()).unwrap();
+        assert_eq!(store.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_iterator_sees_store_as_it_was_created() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(6);
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        let other = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&facts[..3]).unwrap();
+
+        let mut iter = store.try_iter();
+        assert_eq!(iter.next().unwrap().unwrap(), facts[0]);
+        store.append(facts[3].clone()).unwrap();
+        other.append(facts[4].clone()).unwrap();
+        let read: Vec<_> = iter.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, facts[1..3]);
+
+        iter.refresh();
+        other.append(facts[5].clone()).unwrap();
+        let read: Vec<_> = iter.by_ref().map(Result::unwrap).collect();
+        assert_eq!(read, facts[3..5]);
+        assert_eq!(iter.seq(), Some(4));
+
+        // Refreshing before the end just moves it
+        let mut iter = store.iter_from_seq(1);
+        assert_eq!(iter.next(), Some(facts[1].clone()));
+        iter.refresh();
+        assert_eq!(iter.collect::<Vec<_>>(), facts[2..]);
+    }
+
+    #[test]
+    fn test_refresh_picks_up_new_segments() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let facts = create_many_facts(20);
+        let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500)).unwrap();
+        store.append_batch(&facts[..2]).unwrap();
+
+        let mut iter = store.iter();
+        for fact in &facts[2..] {
+            store.append(fact.clone()).unwrap();
+        }
+        assert!(std::fs::read_dir(dir.path()).unwrap().count() > 2);
+        assert_eq!(iter.by_ref().collect::<Vec<_>>(), facts[..2]);
+
+        iter.refresh();
+        assert_eq!(iter.collect::<Vec<_>>(), facts[2..]);
+    }
+
+    #[test]
+    fn test_iterator_with_shared_lock_keeps_writers_out() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(3);
+        let store = FactStore::open_or_create(temp_file.path()).unwrap();
+        store.append_batch(&facts[..2]).unwrap();
+
+        let mut iter = store.iter().with_shared_lock();
+        assert_eq!(iter.next(), Some(facts[0].clone()));
+        assert!(matches!(
+            store.append(facts[2].clone()),
+            Err(StoreError::Write(WriteError::AlreadyLocked))
+        ));
+        assert_eq!(iter.collect::<Vec<_>>(), facts[1..2]);
+
+        store.append(facts[2].clone()).unwrap();
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+    }
+
+    #[test]
+    fn test_segmented_iter_from_skips_sealed_segments() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let store = FactStore::open_segmented(dir.path(), SegmentPolicy::by_size(500)).unwrap();
+        let facts = create_many_facts(40);
+        store.append_batch(&facts[..20]).unwrap();
+        store.append_batch(&facts[20..]).unwrap();
+
+        // Damage the sealed segment: skipping it must avoid reading it at all
+        let sealed = dir.path().join("0000000000.facts");
+        append_garbage(&sealed, b"not a fact\n");
+
+        let read_facts: Result<Vec<_>, _> = store.try_iter_from(*facts[20].timestamp()).collect();
+        assert_eq!(read_facts.unwrap(), facts[20..]);
+        assert!(store.try_iter().any(|fact| fact.is_err()));
+    }
+
+    #[test]
+    fn test_segmented_store_latest_timestamp_from_sealed_segment() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(100);
+        let facts = create_many_facts(3);
+
+        {
+            let store = FactStore::open_segmented(dir.path(), policy).unwrap();
+            store.append_batch(&facts).unwrap();
+            // Rolls to a fresh segment, then removing it leaves it empty
+            store.append(facts[2].clone()).unwrap();
+        }
+        std::fs::remove_file(dir.path().join("0000000001.facts")).unwrap();
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_segmented(dir.path(), policy).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[2].timestamp()));
+        assert_eq!(store.iter().count(), 3);
+    }
+
+    /// JSON records ended by an ASCII record separator instead of a newline
+    #[derive(Clone)]
+    struct SeparatedJson;
+
+    impl Codec for SeparatedJson {
+        const DELIMITER: u8 = 0x1e;
+
+        fn encode<T: Serialize>(
+            &self,
+            value: &T,
+            _checksum: bool,
+            out: &mut Vec<u8>,
+        ) -> Result<(), crate::CodecError> {
+            serde_json::to_writer(&mut *out, value)?;
+            out.push(Self::DELIMITER);
+            Ok(())
+        }
+
+        fn decode<T: DeserializeOwned>(&self, record: &[u8]) -> Result<T, crate::CodecError> {
+            Ok(serde_json::from_slice(record)?)
+        }
+
+        fn has_checksum(&self, _record: &[u8]) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_store_with_custom_codec() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(50);
+
+        {
+            let store = FactStore::open_with_codec(temp_file.path(), SeparatedJson)
+                .unwrap()
+                .with_batch_framing(true);
+            store.append_batch(&facts[..20]).unwrap();
+            for fact in &facts[20..] {
+                store.append(fact.clone()).unwrap();
+            }
+        }
+
+        let contents = std::fs::read(temp_file.path()).unwrap();
+        assert!(!contents.contains(&b'\n'));
+        assert_eq!(contents.iter().filter(|&&b| b == 0x1e).count(), 31);
+
+        let store: FactStore<String, TestValue, String, SeparatedJson> =
+            FactStore::open_with_codec(temp_file.path(), SeparatedJson).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[49].timestamp()));
+
+        let read_facts: Vec<_> = store.iter_from(*facts[35].timestamp()).collect();
+        assert_eq!(read_facts, facts[35..]);
+        assert!(store.verify().unwrap().is_ok());
+
+        // A JSON store sees the whole file as one incomplete line
+        let json: FactStore<String, TestValue, String> =
+            FactStore::open_with_recovery(temp_file.path(), RecoveryPolicy::Truncate)
+                .unwrap()
+                .0;
+        assert_eq!(json.iter().count(), 0);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_segments_read_transparently() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(500).with_compression(3);
+        let facts = create_many_facts(40);
+
+        {
+            let store = FactStore::open_segmented(dir.path(), policy).unwrap();
+            for fact in &facts[..30] {
+                store.append(fact.clone()).unwrap();
+            }
+        }
+
+        let store: FactStore<String, TestValue, String> =
+            FactStore::open_segmented(dir.path(), policy).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[29].timestamp()));
+        for fact in &facts[30..] {
+            store.append(fact.clone()).unwrap();
+        }
+
+        let compressed = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().ends_with(".zst")
+            })
+            .count();
+        assert!(compressed >= 3);
+
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+        let read_facts: Vec<_> = store.iter_from(*facts[17].timestamp()).collect();
+        assert_eq!(read_facts, facts[17..]);
+        assert!(store.verify().unwrap().is_ok());
+
+        // A cursor into a compressed segment resumes by decompressing up to it
+        let mut iter = store.iter();
+        iter.nth(6).unwrap();
+        let read_facts: Vec<_> = store.iter_from_cursor(iter.cursor().unwrap()).collect();
+        assert_eq!(read_facts, facts[7..]);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compress_sealed_reports_failures() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let policy = SegmentPolicy::by_size(500).with_compression(3);
+        let facts = create_many_facts(20);
+        let store = FactStore::open_segmented(dir.path(), policy).unwrap();
+
+        // Nothing can be written where the first compressed copy goes
+        let blocker = dir
+            .path()
+            .join(format!("0000000000.facts.zst.{}.tmp", std::process::id()));
+        std::fs::create_dir(&blocker).unwrap();
+        for fact in &facts {
+            store.append(fact.clone()).unwrap();
+        }
+        assert!(dir.path().join("0000000000.facts").exists());
+        assert!(matches!(
+            store.compress_sealed(),
+            Err(StoreError::Write(WriteError::Io(_)))
+        ));
+
+        std::fs::remove_dir(&blocker).unwrap();
+        store.compress_sealed().unwrap();
+        assert!(!dir.path().join("0000000000.facts").exists());
+        assert!(dir.path().join("0000000000.facts.zst").exists());
+        assert_eq!(store.iter().collect::<Vec<_>>(), facts);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_message_pack_store() {
+        use crate::MessagePackCodec;
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let facts = create_many_facts(40);
+        {
+            let store = FactStore::open_with_codec(temp_file.path(), MessagePackCodec)
+                .unwrap()
+                .with_checksums(true);
+            for fact in &facts[..30] {
+                store.append(fact.clone()).unwrap();
+            }
+        }
+        // A write torn part way through a record
+        let torn = common::serialize_batch(&MessagePackCodec, &facts[30..], FRAMED).unwrap();
+        append_garbage(temp_file.path(), &torn[..torn.len() / 2]);
+
+        let store: FactStore<String, TestValue, String, MessagePackCodec> =
+            FactStore::open_with_codec(temp_file.path(), MessagePackCodec).unwrap();
+        assert_eq!(store.latest_timestamp(), Some(*facts[29].timestamp()));
+
+        store.append_batch(&facts[30..]).unwrap();
+        let read_facts: Vec<_> = store.iter_from(*facts[25].timestamp()).collect();
+        assert_eq!(read_facts, facts[25..]);
+        assert!(store.verify().unwrap().is_ok());
+    }
 }
@@ -0,0 +1,139 @@
+// stainless-facts: subscriptions to AsyncFactStore appends
+// ========================================================
+//
+// Committed batches broadcast to in-process subscribers, with a resync
+// from disk for subscribers that fall behind.
+
+use crate::async_store::{AsyncFactStore, AsyncTryFactIterator};
+use crate::io::Codec;
+use crate::Fact;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::broadcast;
+
+/// A batch of facts appended through an [`AsyncFactStore`], once on disk.
+#[derive(Debug)]
+pub struct CommittedBatch<E, V, S> {
+    facts: Arc<[Fact<E, V, S>]>,
+    /// Byte offset just past the batch in the store file
+    end: u64,
+}
+
+// Cloning shares the facts, so no bounds on E, V and S are needed
+impl<E, V, S> Clone for CommittedBatch<E, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            facts: Arc::clone(&self.facts),
+            end: self.end,
+        }
+    }
+}
+
+impl<E, V, S> CommittedBatch<E, V, S> {
+    pub(crate) fn new(facts: Arc<[Fact<E, V, S>]>, end: u64) -> Self {
+        Self { facts, end }
+    }
+
+    /// The facts of the batch, in the order they were appended.
+    pub fn facts(&self) -> &[Fact<E, V, S>] {
+        &self.facts
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SubscriptionError {
+    /// Batches were dropped because the subscriber fell behind.
+    ///
+    /// Nothing more is received until [`Subscription::resync`] has read the
+    /// missed facts from disk.
+    #[error("Subscriber fell behind and missed {0} batches; resync from disk")]
+    Lagged(u64),
+
+    /// The store was dropped, so no more batches will be committed.
+    #[error("The store has been closed")]
+    Closed,
+}
+
+/// Receiver of the batches committed by an [`AsyncFactStore`].
+///
+/// Created by [`AsyncFactStore::subscribe`]. Every batch appended after
+/// subscribing is received once, in the order it was written. Batches are
+/// buffered up to the store's subscriber capacity; a subscriber that falls
+/// further behind gets [`SubscriptionError::Lagged`] and reads what it
+/// missed from disk with [`Subscription::resync`], after which receiving
+/// carries on with no gap and no duplicates.
+///
+/// Only appends made through the same `AsyncFactStore` are broadcast.
+/// Facts appended by other processes show up in a resync, but are not
+/// received otherwise.
+pub struct Subscription<E, V, S> {
+    receiver: broadcast::Receiver<CommittedBatch<E, V, S>>,
+    /// Byte offset just past everything delivered so far
+    position: u64,
+    /// Batches missed since the last resync, if any
+    missed: Option<u64>,
+}
+
+impl<E, V, S> Subscription<E, V, S> {
+    pub(crate) fn new(
+        receiver: broadcast::Receiver<CommittedBatch<E, V, S>>,
+        position: u64,
+    ) -> Self {
+        Self {
+            receiver,
+            position,
+            missed: None,
+        }
+    }
+
+    /// Wait for the next committed batch.
+    ///
+    /// Cancellation-safe. After a lag this keeps returning
+    /// [`SubscriptionError::Lagged`] until [`Subscription::resync`] is called.
+    pub async fn recv(&mut self) -> Result<CommittedBatch<E, V, S>, SubscriptionError> {
+        if let Some(missed) = self.missed {
+            return Err(SubscriptionError::Lagged(missed));
+        }
+        match self.receiver.recv().await {
+            Ok(batch) => {
+                self.position = batch.end;
+                Ok(batch)
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                self.missed = Some(missed);
+                Err(SubscriptionError::Lagged(missed))
+            }
+            Err(broadcast::error::RecvError::Closed) => Err(SubscriptionError::Closed),
+        }
+    }
+
+    /// Whether batches were missed and a resync is needed.
+    pub fn is_lagged(&self) -> bool {
+        self.missed.is_some()
+    }
+
+    /// Read every fact committed since the last one received, from disk.
+    ///
+    /// The returned iterator yields the facts of the missed batches and
+    /// ends with the last batch committed when `resync` was called; later
+    /// batches are received again through [`Subscription::recv`]. Drain the
+    /// iterator before receiving, or facts will be delivered out of order.
+    /// Resyncing when nothing was missed yields whatever was committed but
+    /// not received yet.
+    pub async fn resync<C>(
+        &mut self,
+        store: &AsyncFactStore<E, V, S, C>,
+    ) -> AsyncTryFactIterator<E, V, S, C>
+    where
+        C: Codec,
+        E: Serialize + DeserializeOwned + Clone,
+        V: Serialize + DeserializeOwned + Clone,
+        S: Serialize + DeserializeOwned + Clone,
+    {
+        // Start receiving afresh right after what is read from disk
+        let start = self.position;
+        *self = store.subscribe();
+        store.iter_between(start, self.position).await
+    }
+}
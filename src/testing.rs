@@ -0,0 +1,103 @@
+// stainless-facts: fixtures shared by the unit tests
+// ==================================================
+
+use crate::{Fact, Operation};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "t", content = "v")]
+pub(crate) enum TestValue {
+    Count(u32),
+}
+
+pub(crate) type TestFact = Fact<String, TestValue, String>;
+
+/// `seconds` into the fixtures, which start at 2024-01-15T10:00:00Z.
+pub(crate) fn at(seconds: i64) -> DateTime<Utc> {
+    DateTime::<Utc>::from_timestamp(1_705_312_800 + seconds, 0).unwrap()
+}
+
+/// An asserted or retracted fact about `entity` from `source1`.
+pub(crate) fn fact<V>(
+    entity: impl Into<String>,
+    value: V,
+    timestamp: DateTime<Utc>,
+    operation: Operation,
+) -> Fact<String, V, String> {
+    Fact::new(
+        entity.into(),
+        value,
+        timestamp,
+        "source1".to_string(),
+        operation,
+    )
+}
+
+/// One fact per second in `seconds`, about `item{second}` and with the
+/// second as its value.
+pub(crate) fn item_facts(seconds: impl IntoIterator<Item = i64>) -> Vec<Fact<String, i64, String>> {
+    seconds
+        .into_iter()
+        .map(|second| {
+            fact(
+                format!("item{second}"),
+                second,
+                at(second),
+                Operation::Assert,
+            )
+        })
+        .collect()
+}
+
+/// `item1`, `item2` and `item3`, one minute apart.
+pub(crate) fn create_test_facts() -> Vec<TestFact> {
+    (1..=3)
+        .map(|i| {
+            fact(
+                format!("item{i}"),
+                TestValue::Count(i),
+                at(60 * i64::from(i - 1)),
+                Operation::Assert,
+            )
+        })
+        .collect()
+}
+
+/// `count` facts about `item0`, `item1`, ..., one second apart.
+pub(crate) fn create_many_facts(count: u32) -> Vec<TestFact> {
+    (0..count)
+        .map(|i| {
+            fact(
+                format!("item{i}"),
+                TestValue::Count(i),
+                at(i64::from(i)),
+                Operation::Assert,
+            )
+        })
+        .collect()
+}
+
+/// A fact about `entity` whose timestamp is `value` seconds into the fixtures.
+pub(crate) fn entity_fact(
+    entity: &str,
+    value: u32,
+    operation: Operation,
+) -> Fact<String, u32, String> {
+    fact(entity, value, at(i64::from(value)), operation)
+}
+
+/// `count` facts about `entity0`, `entity1` and `entity2` in turn, one second
+/// apart, each with its position as its value.
+pub(crate) fn create_entity_facts(count: u32) -> Vec<Fact<String, u32, String>> {
+    (0..count)
+        .map(|i| entity_fact(&format!("entity{}", i % 3), i, Operation::Assert))
+        .collect()
+}
+
+/// Append raw bytes to the file at `path`, behind the writers' backs.
+pub(crate) fn append_garbage(path: &Path, garbage: &[u8]) {
+    let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+    std::io::Write::write_all(&mut file, garbage).unwrap();
+}